@@ -0,0 +1,41 @@
+//! Times `set_visibility` on small and large items.
+//!
+//! Run with `cargo bench -p visibility-attribute-core`. The large item
+//! has its tokens at the top level, rather than inside a group, so every
+//! pass over the item's tokens shows up in the time.
+
+use proc_macro2::TokenStream;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use visibility_attribute_core::set_visibility;
+
+/// The mean time of `f` over the fastest of a few rounds, which is the
+/// least disturbed by anything else running.
+fn time(iterations: u32, mut f: impl FnMut()) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                f();
+            }
+            start.elapsed() / iterations
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let small: TokenStream = "#[inline] pub fn f(a: i32) -> i32 { a + 1 }"
+        .parse()
+        .unwrap();
+    let sum = "1 + ".repeat(5000);
+    let large: TokenStream = format!("pub static X: u32 = {sum} 1;").parse().unwrap();
+
+    for (name, item, iterations) in [("small", small, 100_000), ("10k tokens", large, 1_000)] {
+        let visibility: TokenStream = "pub(crate)".parse().unwrap();
+        let elapsed = time(iterations, || {
+            black_box(set_visibility(visibility.clone(), item.clone()));
+        });
+        println!("{name}: {elapsed:?}");
+    }
+}
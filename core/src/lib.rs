@@ -0,0 +1,2324 @@
+//! Token manipulation behind `visibility_attribute`.
+//!
+//! Procedural macro crates can only export macros, so the logic lives
+//! here for other crates, such as build scripts and other procedural
+//! macros, to use directly. Everything operates on [`proc_macro2`]
+//! tokens, which convert to and from [`proc_macro`] ones.
+//!
+//! [`proc_macro`]: https://doc.rust-lang.org/proc_macro/index.html
+//!
+//! Only the crate's own code is `no_std`, needing just `alloc`, which
+//! every build but that of its unit tests checks. `proc_macro2` itself
+//! still links to `std`, so the crate can't be built for a target
+//! without it, and there is no such target to test against.
+//!
+//! The [`prelude`] brings the most used items into scope at once.
+//!
+//! # Features
+//! - `testing`: Expose the [`testing`] module, for test suites of crates
+//!   built on top of this one.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter::Peekable;
+use proc_macro2::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+pub mod prelude;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(test)]
+mod tests;
+
+/// Removes the visibility modifier from an item.
+///
+/// Any outer attributes before the visibility are kept in place. Items
+/// without a visibility are returned unchanged.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::strip_visibility;
+///
+/// let item = "#[inline] pub(crate) fn f() {}".parse().unwrap();
+/// assert_eq!(strip_visibility(item).to_string(), "# [inline] fn f () { }");
+/// ```
+pub fn strip_visibility(ts: TokenStream) -> TokenStream {
+    remove_visibility(ts).rest().collect()
+}
+
+/// Returns the visibility modifier of an item, or `None` if it is private.
+///
+/// Outer attributes before the visibility are skipped. The item itself
+/// isn't modified.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::peek_visibility;
+///
+/// let item = "#[inline] pub(crate) fn f() {}".parse().unwrap();
+/// assert_eq!(peek_visibility(&item).unwrap().to_string(), "pub (crate)");
+/// ```
+pub fn peek_visibility(item: &TokenStream) -> Option<TokenStream> {
+    let visibility = remove_visibility(item.clone()).visibility;
+    if visibility.is_empty() {
+        None
+    } else {
+        Some(visibility.into_iter().collect())
+    }
+}
+
+/// Describes how [`set_visibility`] changed an item, given the item and
+/// the output.
+///
+/// If the visibility had an `if` and a `cfg` predicate, each copy of the
+/// item is described with its predicate. Output with a `compile_error!`
+/// didn't change anything, so it gives `None`.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::{describe_change, set_visibility};
+///
+/// let item: proc_macro2::TokenStream = "pub fn f() {}".parse().unwrap();
+/// let output = set_visibility("pub(crate)".parse().unwrap(), item.clone());
+/// assert_eq!(
+///     describe_change(&item, &output).unwrap(),
+///     "`set_visibility` changed `pub` to `pub (crate)`"
+/// );
+/// ```
+pub fn describe_change(annotated_item: &TokenStream, output: &TokenStream) -> Option<String> {
+    let describe = |x: &[TokenTree]| {
+        let visibility = remove_visibility(x.iter().cloned()).visibility;
+        if visibility.is_empty() {
+            "private".to_owned()
+        } else {
+            format!("`{}`", visibility.into_iter().collect::<TokenStream>())
+        }
+    };
+    // The items added next to the item, such as for `debug`, are unnamed
+    // constants.
+    let is_unnamed_const = |x: &[TokenTree]| {
+        let mut item = remove_visibility(x.iter().cloned()).item;
+        matches!((item.next(), item.next()), (Some(y), Some(z)) if is_ident(&y, "const") && is_ident(&z, "_"))
+    };
+
+    let items = split_items(output.clone());
+    if items.iter().any(|x| is_compile_error(x)) {
+        return None;
+    }
+    let copies: Vec<Vec<TokenTree>> = items.into_iter().filter(|x| !is_unnamed_const(x)).collect();
+    let after = match &copies[..] {
+        [] => return None,
+        [item] => describe(item),
+        // Each copy starts with the `#[cfg]` it was given.
+        _ => {
+            let conditions: Vec<String> = copies
+                .iter()
+                .map(|item| {
+                    let predicate = match item.get(1) {
+                        Some(TokenTree::Group(x)) => match x.stream().into_iter().nth(1) {
+                            Some(TokenTree::Group(y)) => y.stream(),
+                            _ => TokenStream::new(),
+                        },
+                        _ => TokenStream::new(),
+                    };
+                    format!("{} if `{predicate}`", describe(item))
+                })
+                .collect();
+            conditions.join(", and to ")
+        }
+    };
+    let before: Vec<TokenTree> = annotated_item.clone().into_iter().collect();
+    Some(format!(
+        "`set_visibility` changed {} to {after}",
+        describe(&before)
+    ))
+}
+
+/// Inserts a visibility modifier into an item that doesn't have one.
+///
+/// The visibility goes after any outer attributes. Nothing is checked,
+/// which suits macros stamping a visibility onto items they generate
+/// themselves, such as accessor methods. Use [`set_visibility`] for items
+/// that might already have a visibility.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::prepend_visibility;
+///
+/// let visibility = "pub(crate)".parse().unwrap();
+/// let item = "#[inline] fn x(&self) -> i32 { self.x }".parse().unwrap();
+/// assert_eq!(
+///     prepend_visibility(&visibility, item).to_string(),
+///     "# [inline] pub (crate) fn x (& self) -> i32 { self . x }"
+/// );
+/// ```
+pub fn prepend_visibility(visibility: &TokenStream, item: TokenStream) -> TokenStream {
+    let mut tt_iter = item.into_iter().peekable();
+    let mut out_stream = Vec::new();
+    take_attributes(&mut tt_iter, &mut out_stream);
+    out_stream.extend(visibility.clone());
+    out_stream.extend(tt_iter);
+    out_stream.into_iter().collect()
+}
+
+/// Replaces the visibility modifier of an item.
+///
+/// This is the logic behind the `set_visibility` attribute. `input` is
+/// the attribute's argument: a visibility followed by any options. If it
+/// is invalid, or the item can't take a visibility, a `compile_error!`
+/// invocation is returned along with the unchanged item.
+///
+/// An `input` with no tokens, such as one of only whitespace or comments,
+/// makes the item private. Options given without a visibility work as
+/// if it were `pub(self)`, apart from `keep_if_missing_arg`.
+///
+/// Replacing a visibility is idempotent: applying the same visibility
+/// to the output again gives the same output, so it is safe for
+/// composed macros to apply it more than once.
+///
+/// # Spans
+/// The new visibility takes the span of the visibility it replaces, or
+/// keeps its span from `input` if the item was private. The item's
+/// tokens keep their spans, and tokens added for options, such as
+/// `#[allow(dead_code)]`, get [`Span::call_site`]. The output is the same
+/// for the same input, so expansion snapshots are stable.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::set_visibility;
+///
+/// let input = "pub(crate)".parse().unwrap();
+/// let item = "pub fn f() {}".parse().unwrap();
+/// assert_eq!(set_visibility(input, item).to_string(), "pub (crate) fn f () { }");
+/// ```
+pub fn set_visibility(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    try_set_visibility(input, annotated_item.clone()).unwrap_or_else(|error| {
+        let mut out_stream = error.into_compile_error();
+        out_stream.extend(annotated_item);
+        out_stream
+    })
+}
+
+/// Replaces the visibility modifier of an item, or returns why it can't.
+///
+/// This is the same as [`set_visibility`], but leaves it to the caller
+/// to report any error.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::{try_set_visibility, VisibilityError};
+///
+/// let item: proc_macro2::TokenStream = "pub fn f() {}".parse().unwrap();
+/// let output = try_set_visibility("pub(crate)".parse().unwrap(), item.clone());
+/// assert_eq!(output.unwrap().to_string(), "pub (crate) fn f () { }");
+///
+/// let output = try_set_visibility("crate::a".parse().unwrap(), item);
+/// assert!(matches!(output, Err(VisibilityError::InvalidVisibility { .. })));
+/// ```
+pub fn try_set_visibility(
+    input: TokenStream,
+    annotated_item: TokenStream,
+) -> Result<TokenStream, VisibilityError> {
+    let output = parse_arguments(input)
+        .map_err(|(message, span)| VisibilityError::InvalidVisibility { message, span })?
+        .try_apply(annotated_item)?;
+    debug_assert_relexes(&output);
+    Ok(output)
+}
+
+/// Checks, in debug builds, that the output of a rewrite can be printed
+/// and lexed again, since anything else is a bug in the splicing.
+fn debug_assert_relexes(output: &TokenStream) {
+    if cfg!(debug_assertions) {
+        let text = output.to_string();
+        assert!(
+            text.parse::<TokenStream>().is_ok(),
+            "`set_visibility` generated tokens that don't lex: {text}"
+        );
+    }
+}
+
+/// Parses the argument of the `set_visibility` attribute.
+///
+/// The argument is a comma-separated list of a visibility and options,
+/// in any order. Empty entries, such as from a trailing comma, are
+/// ignored, and a missing visibility means private. The visibility can
+/// be followed by `if` and a `cfg` predicate.
+fn parse_arguments(input: TokenStream) -> Result<VisibilityRewriter, (&'static str, Span)> {
+    let mut rewriter = VisibilityRewriter::new();
+    let mut visibility = None;
+    let mut keep_if_missing = false;
+
+    for (arg, _) in split_commas(input) {
+        rewriter = match &arg[..] {
+            [] => rewriter,
+            [x] if is_ident(x, "fields") => rewriter.fields(),
+            [x] if is_ident(x, "warn_noop") => rewriter.warn_noop(),
+            [x] if is_ident(x, "only_if_private") => rewriter.only_if_private(),
+            [x] if is_ident(x, "allow_dead") => rewriter.allow_dead(),
+            [x] if is_ident(x, "recursive") => rewriter.recursive(),
+            [x] if is_ident(x, "descend") => rewriter.descend(),
+            [x] if is_ident(x, "debug") => rewriter.debug(),
+            [x] if is_ident(x, "strip_doc_hidden") => rewriter.strip_doc_hidden(),
+            [x] if is_ident(x, "normalize") => rewriter.normalize(),
+            [x] if is_ident(x, "keep_if_missing_arg") => {
+                keep_if_missing = true;
+                rewriter
+            }
+            [x, TokenTree::Punct(y), TokenTree::Literal(z)]
+                if is_ident(x, "field") && y.as_char() == '=' =>
+            {
+                match unquote(z) {
+                    Some(name) => rewriter.field(name),
+                    None => return Err(("expected a field name string", z.span())),
+                }
+            }
+            [x, TokenTree::Punct(y), TokenTree::Literal(z)]
+                if is_ident(x, "index") && y.as_char() == '=' =>
+            {
+                match z.to_string().parse() {
+                    Ok(index) => rewriter.index(index),
+                    Err(_) => return Err(("expected a field index", z.span())),
+                }
+            }
+            [x, TokenTree::Punct(y), TokenTree::Literal(z)]
+                if is_ident(x, "max_tokens") && y.as_char() == '=' =>
+            {
+                match z.to_string().replace('_', "").parse() {
+                    Ok(count) => rewriter.max_tokens(count),
+                    Err(_) => return Err(("expected a token count", z.span())),
+                }
+            }
+            [x, TokenTree::Punct(y), rest @ ..] if is_ident(x, "fields") && y.as_char() == '=' => {
+                rewriter.with_field_visibility(rest.iter().cloned().collect())
+            }
+            [x, TokenTree::Punct(y), TokenTree::Literal(z)]
+                if is_ident(x, "from_env") && y.as_char() == '=' && unquote(z).is_some() =>
+            {
+                return Err((
+                    "`from_env` needs the environment; call `resolve_from_env` first",
+                    x.span(),
+                ))
+            }
+            [x, ..] if is_ident(x, "from_env") => {
+                return Err(("expected an environment variable name string", x.span()))
+            }
+            [x, ..] if visibility.is_some() => {
+                return Err(("unknown `set_visibility` option", x.span()))
+            }
+            _ => {
+                let mut arg = arg;
+                if let Some(i) = arg.iter().position(|x| is_ident(x, "if")) {
+                    let mut predicate = arg.split_off(i);
+                    let keyword = predicate.remove(0);
+                    if predicate.is_empty() {
+                        return Err(("expected a `cfg` predicate after `if`", keyword.span()));
+                    }
+                    rewriter = rewriter.when(predicate.into_iter().collect());
+                }
+                visibility = Some(arg.into_iter().collect());
+                rewriter
+            }
+        };
+    }
+
+    Ok(match visibility {
+        Some(x) => rewriter.with_visibility(x),
+        // Generated code may leave out the visibility to mean "unchanged".
+        None if keep_if_missing => {
+            let inherit = TokenTree::from(Ident::new("inherit", Span::call_site()));
+            rewriter.with_visibility(inherit.into())
+        }
+        None => rewriter,
+    })
+}
+
+/// A reusable visibility transformation.
+///
+/// The options of the `set_visibility` attribute are set once with the
+/// builder methods, and then [`apply`](Self::apply) can be called on any
+/// number of items. By default, it makes items private.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::VisibilityRewriter;
+///
+/// let rewriter = VisibilityRewriter::new().with_visibility("pub(crate)".parse().unwrap());
+/// for item in ["fn f() {}", "pub struct S;"] {
+///     let out = rewriter.apply(item.parse().unwrap()).to_string();
+///     assert!(out.starts_with("pub (crate)"));
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct VisibilityRewriter {
+    visibility: TokenStream,
+    fields: bool,
+    only_if_private: bool,
+    warn_noop: bool,
+    allow_dead: bool,
+    field: Option<String>,
+    predicate: Option<TokenStream>,
+    recursive: bool,
+    field_visibility: Option<TokenStream>,
+    descend: bool,
+    debug: bool,
+    strip_doc_hidden: bool,
+    normalize: bool,
+    max_tokens: Option<usize>,
+    index: Option<usize>,
+}
+
+impl VisibilityRewriter {
+    /// Creates a rewriter that makes items private.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the visibility to give items, in any form the
+    /// `set_visibility` attribute accepts.
+    pub fn with_visibility(mut self, visibility: TokenStream) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    /// Only removes the visibility, making items private.
+    pub fn strip_only(mut self) -> Self {
+        self.visibility = TokenStream::new();
+        self
+    }
+
+    /// Sets the visibility of each field of a struct or union, or each
+    /// declaration of an `extern` block, instead of the item itself.
+    pub fn fields(mut self) -> Self {
+        self.fields = true;
+        self
+    }
+
+    /// Sets the visibility of each field of a struct to a different
+    /// visibility than the struct itself.
+    pub fn with_field_visibility(mut self, visibility: TokenStream) -> Self {
+        self.field_visibility = Some(visibility);
+        self
+    }
+
+    /// Sets the visibility of just the named field of a struct instead
+    /// of the struct itself.
+    pub fn field(mut self, name: impl Into<String>) -> Self {
+        self.field = Some(name.into());
+        self
+    }
+
+    /// Sets the visibility of just the field of a tuple struct at
+    /// `index`, counting from 0, instead of the struct itself.
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Leaves items that already have a visibility alone.
+    pub fn only_if_private(mut self) -> Self {
+        self.only_if_private = true;
+        self
+    }
+
+    /// Causes a deprecation warning if the visibility wouldn't change.
+    pub fn warn_noop(mut self) -> Self {
+        self.warn_noop = true;
+        self
+    }
+
+    /// Allows dead code on items made private, since they are often
+    /// unused in the builds that hide them.
+    pub fn allow_dead(mut self) -> Self {
+        self.allow_dead = true;
+        self
+    }
+
+    /// Also rewrites the items inside a module, and those inside its
+    /// nested modules.
+    ///
+    /// Items that can't take a visibility, such as `impl` blocks and
+    /// macro invocations, are left alone.
+    pub fn recursive(mut self) -> Self {
+        self.recursive = true;
+        self
+    }
+
+    /// Rewrites the item inside a brace or invisible group, when that
+    /// group is all there is, and wraps it back up.
+    ///
+    /// Other procedural macros sometimes pass items along wrapped up in
+    /// a group, which would otherwise hide the visibility.
+    pub fn descend(mut self) -> Self {
+        self.descend = true;
+        self
+    }
+
+    /// Removes `#[doc(hidden)]` from items that end up less visible than
+    /// `pub`, since only public items show up in documentation.
+    pub fn strip_doc_hidden(mut self) -> Self {
+        self.strip_doc_hidden = true;
+        self
+    }
+
+    /// Writes `pub(in crate)` and `pub(in super)` as the shorter
+    /// `pub(crate)` and `pub(super)`.
+    pub fn normalize(mut self) -> Self {
+        self.normalize = true;
+        self
+    }
+
+    /// Reports items of more than `count` tokens, counting those inside
+    /// groups, as an error.
+    ///
+    /// This guards against rewriting huge generated items by accident.
+    pub fn max_tokens(mut self, count: usize) -> Self {
+        self.max_tokens = Some(count);
+        self
+    }
+
+    /// Adds a `const _: &str` item to the output describing the
+    /// visibility before and after, to find out which of several
+    /// rewrites took effect.
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    /// Only rewrites items when the `cfg` predicate, such as
+    /// `feature = "expose"`, holds.
+    ///
+    /// The predicate is evaluated by the compiler, so the output contains
+    /// both the rewritten and the original item, each behind a `cfg`.
+    pub fn when(mut self, predicate: TokenStream) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Rewrites the visibility of an item.
+    ///
+    /// If the visibility is invalid, or the item can't take one, a
+    /// `compile_error!` invocation is returned along with the unchanged
+    /// item.
+    pub fn apply(&self, annotated_item: TokenStream) -> TokenStream {
+        self.try_apply(annotated_item.clone())
+            .unwrap_or_else(|error| {
+                let mut out_stream = error.into_compile_error();
+                out_stream.extend(annotated_item);
+                out_stream
+            })
+    }
+
+    /// Rewrites the visibility of an item, or returns why it can't.
+    pub fn try_apply(&self, annotated_item: TokenStream) -> Result<TokenStream, VisibilityError> {
+        // Checked first, so anything added for the other options, such as
+        // the `debug` item, is behind the same `cfg`.
+        if let Some(predicate) = &self.predicate {
+            let unconditional = Self {
+                predicate: None,
+                ..self.clone()
+            };
+            let transformed = unconditional.try_apply(annotated_item.clone())?;
+            return Ok(select_by_cfg(predicate, transformed, annotated_item));
+        }
+
+        if self.debug {
+            let quiet = Self {
+                debug: false,
+                ..self.clone()
+            };
+            let mut out_stream = quiet.try_apply(annotated_item.clone())?;
+            if let Some(description) = describe_change(&annotated_item, &out_stream) {
+                let item = format!("const _: &str = {};", Literal::string(&description));
+                out_stream.extend(item.parse::<TokenStream>().unwrap());
+            }
+            return Ok(out_stream);
+        }
+
+        if let Some(max_tokens) = self.max_tokens {
+            if count_tokens(annotated_item.clone()) > max_tokens {
+                return Err(VisibilityError::UnsupportedItem {
+                    message: "the item has more tokens than `max_tokens` allows",
+                    span: annotated_item.into_iter().next().unwrap().span(),
+                });
+            }
+        }
+
+        if self.descend {
+            let mut tt_iter = annotated_item.clone().into_iter();
+            if let (Some(TokenTree::Group(group)), None) = (tt_iter.next(), tt_iter.next()) {
+                if matches!(group.delimiter(), Delimiter::Brace | Delimiter::None) {
+                    let inner = Self {
+                        descend: false,
+                        ..self.clone()
+                    };
+                    let mut out = Group::new(group.delimiter(), inner.try_apply(group.stream())?);
+                    out.set_span(group.span());
+                    return Ok(TokenTree::from(out).into());
+                }
+            }
+        }
+
+        let invalid = |message, span| VisibilityError::InvalidVisibility { message, span };
+        let unsupported = |(message, span)| VisibilityError::UnsupportedItem { message, span };
+
+        let visibility = match unquote_visibility(self.visibility.clone()) {
+            Ok(x) => self.normalize_visibility(x),
+            Err(span) => return Err(invalid("expected a string containing a visibility", span)),
+        };
+        // `inherit` explicitly keeps the existing visibility.
+        let mut vis_iter = visibility.clone().into_iter();
+        let inherit = match (vis_iter.next(), vis_iter.next()) {
+            (Some(x), None) => is_ident(&x, "inherit"),
+            _ => false,
+        };
+        if !inherit {
+            validate_visibility(visibility.clone())
+                .map_err(|span| invalid(invalid_visibility_message(&visibility), span))?;
+        }
+
+        // The item is only copied once, into `tokens`, as copying every
+        // token of a large item is most of the time taken.
+        let mut tokens: Vec<TokenTree> = annotated_item.into_iter().collect();
+        // `fields` is the one way to apply the visibility to an `extern`
+        // block, which is otherwise reported.
+        if !(self.fields && extern_block_body(&tokens).is_some()) {
+            check_item_kind(&tokens).map_err(unsupported)?;
+        }
+
+        if let Some(field_visibility) = &self.field_visibility {
+            let field_visibility = match unquote_visibility(field_visibility.clone()) {
+                Ok(x) => self.normalize_visibility(x),
+                Err(span) => {
+                    return Err(invalid("expected a string containing a visibility", span))
+                }
+            };
+            validate_visibility(field_visibility.clone())
+                .map_err(|span| invalid(invalid_visibility_message(&field_visibility), span))?;
+
+            if !inherit {
+                replace_visibility(visibility, &mut tokens, false);
+            }
+            let only = self.field_selector();
+            return set_field_visibility(field_visibility, tokens.into_iter().collect(), only)
+                .map_err(unsupported);
+        }
+
+        if inherit {
+            return Ok(tokens.into_iter().collect());
+        }
+
+        // `only_if_private` is checked for each item of the module, so a
+        // public module still has its private items rewritten.
+        if self.recursive {
+            return self.set_module_visibility(tokens.into_iter().collect());
+        }
+
+        if self.only_if_private
+            && !remove_visibility(tokens.iter().cloned())
+                .visibility
+                .is_empty()
+        {
+            return Ok(tokens.into_iter().collect());
+        }
+
+        if self.fields || self.field.is_some() || self.index.is_some() {
+            let only = self.field_selector();
+            let item = tokens.into_iter().collect();
+            return set_field_visibility(visibility, item, only).map_err(unsupported);
+        }
+
+        let visibility_tokens: Vec<TokenTree> = visibility.clone().into_iter().collect();
+        let existing = replace_visibility(visibility, &mut tokens, false);
+        if self.strip_doc_hidden && VisLevel::of(&visibility_tokens) != Some(VisLevel::Public) {
+            remove_doc_hidden(&mut tokens);
+        }
+        let mut out_stream = TokenStream::new();
+        if self.allow_dead && visibility_tokens.is_empty() {
+            out_stream.extend("#[allow(dead_code)]".parse::<TokenStream>().unwrap());
+        }
+        out_stream.extend(tokens);
+        if self.warn_noop
+            && compare_visibility(&existing, &visibility_tokens) == Some(Ordering::Equal)
+        {
+            let span = existing
+                .first()
+                .or(visibility_tokens.first())
+                .map_or_else(Span::call_site, TokenTree::span);
+            out_stream.extend(noop_warning(span));
+        }
+        Ok(out_stream)
+    }
+
+    /// The single field to rewrite, if `field` or `index` is set.
+    fn field_selector(&self) -> Option<FieldSelector<'_>> {
+        match (&self.field, self.index) {
+            (Some(name), _) => Some(FieldSelector::Name(name)),
+            (None, Some(index)) => Some(FieldSelector::Index(index)),
+            (None, None) => None,
+        }
+    }
+
+    /// Rewrites a visibility into its simplest form, taking the
+    /// `normalize` option into account.
+    fn normalize_visibility(&self, visibility: TokenStream) -> TokenStream {
+        let tokens: Vec<TokenTree> = visibility.clone().into_iter().collect();
+        match &tokens[..] {
+            [x, TokenTree::Group(y)] if self.normalize && is_paren_group(&tokens[1]) => {
+                let inner: Vec<TokenTree> = y.stream().into_iter().collect();
+                match &inner[..] {
+                    // `pub(in self)` becomes `pub(self)`, and so private.
+                    [z, w]
+                        if is_ident(z, "in")
+                            && ["crate", "self", "super"]
+                                .iter()
+                                .any(|name| is_ident(w, name)) =>
+                    {
+                        let mut group = Group::new(Delimiter::Parenthesis, w.clone().into());
+                        group.set_span(y.span());
+                        normalize_visibility([x.clone(), group.into()].into_iter().collect())
+                    }
+                    _ => normalize_visibility(visibility),
+                }
+            }
+            _ => normalize_visibility(visibility),
+        }
+    }
+
+    /// Rewrites the visibility of a module and everything inside it.
+    fn set_module_visibility(
+        &self,
+        annotated_item: TokenStream,
+    ) -> Result<TokenStream, VisibilityError> {
+        let mut stripped = remove_visibility(annotated_item.clone());
+        match stripped.item.next() {
+            Some(x) if is_ident(&x, "mod") => {}
+            x => {
+                return Err(VisibilityError::UnsupportedItem {
+                    message: "`recursive` can only be used on a `mod`",
+                    span: x.map_or_else(Span::call_site, |y| y.span()),
+                })
+            }
+        }
+
+        let shallow = Self {
+            recursive: false,
+            ..self.clone()
+        };
+        let mut tokens: Vec<TokenTree> = shallow.try_apply(annotated_item)?.into_iter().collect();
+        let keyword = tokens.iter().position(|x| is_ident(x, "mod")).unwrap();
+        // The contents of `mod foo;` are in another file.
+        let Some(TokenTree::Group(group)) = tokens.get(keyword + 2) else {
+            return Ok(tokens.into_iter().collect());
+        };
+
+        let contents: Vec<TokenTree> = group.stream().into_iter().collect();
+        let inner_attributes = contents
+            .chunks(3)
+            .take_while(|x| match x {
+                [TokenTree::Punct(a), TokenTree::Punct(b), TokenTree::Group(c)] => {
+                    a.as_char() == '#' && b.as_char() == '!' && c.delimiter() == Delimiter::Bracket
+                }
+                _ => false,
+            })
+            .count();
+        let (attributes, items) = contents.split_at(inner_attributes * 3);
+
+        let plain = Self {
+            fields: false,
+            field: None,
+            ..shallow
+        };
+        let mut new_contents: TokenStream = attributes.iter().cloned().collect();
+        for item in split_items(items.to_vec()) {
+            let mut rest = remove_visibility(item.clone()).item;
+            if rest.next().is_some_and(|x| is_ident(&x, "mod")) {
+                new_contents.extend(self.set_module_visibility(item.into_iter().collect())?);
+            } else if is_without_visibility(&item) {
+                new_contents.extend(item);
+            } else {
+                new_contents.extend(plain.try_apply(item.into_iter().collect())?);
+            }
+        }
+
+        let mut new_group = Group::new(Delimiter::Brace, new_contents);
+        new_group.set_span(group.span());
+        tokens[keyword + 2] = new_group.into();
+        Ok(tokens.into_iter().collect())
+    }
+}
+
+/// Counts the tokens of a stream, including those inside groups, but not
+/// the groups themselves.
+fn count_tokens(stream: TokenStream) -> usize {
+    stream
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Group(x) => count_tokens(x.stream()),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Removes any `#[doc(hidden)]` from the outer attributes of an item.
+fn remove_doc_hidden(tokens: &mut Vec<TokenTree>) {
+    let mut i = 0;
+    while let [TokenTree::Punct(x), TokenTree::Group(y), ..] = &tokens[i..] {
+        if x.as_char() != '#' || y.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        let attribute: Vec<TokenTree> = y.stream().into_iter().collect();
+        match &attribute[..] {
+            [z, TokenTree::Group(w)]
+                if is_ident(z, "doc") && *w.stream().to_string() == *"hidden" =>
+            {
+                tokens.drain(i..i + 2);
+            }
+            _ => i += 2,
+        }
+    }
+}
+
+/// Checks for items that can be in a module but can't take a
+/// visibility, such as `impl` blocks, `extern` blocks and macro
+/// invocations.
+fn is_without_visibility(item: &[TokenTree]) -> bool {
+    let rest: Vec<TokenTree> = remove_visibility(item.iter().cloned())
+        .item
+        .take(2)
+        .collect();
+    match &rest[..] {
+        [x, ..] if is_ident(x, "impl") => true,
+        [x, y] if is_ident(x, "unsafe") && is_ident(y, "impl") => true,
+        [x, y] if is_ident(x, "macro_rules") && is_punct(y, '!') => true,
+        _ => is_macro_invocation(item) || extern_block_body(item).is_some(),
+    }
+}
+
+/// Checks for a macro invocation such as `foo! { .. }` or
+/// `path::to::foo!(..);`, other than a `macro_rules!` definition.
+fn is_macro_invocation(item: &[TokenTree]) -> bool {
+    let mut path = Vec::new();
+    for tt in remove_visibility(item.iter().cloned()).item {
+        if is_punct(&tt, '!') {
+            // A path alternates between segments and the two puncts of `::`.
+            let is_path = path.iter().rev().enumerate().all(|(i, x)| match i % 3 {
+                0 => matches!(x, TokenTree::Ident(_)),
+                _ => is_punct(x, ':'),
+            });
+            let is_definition = matches!(&path[..], [x] if is_ident(x, "macro_rules"));
+            return !path.is_empty() && is_path && !is_definition;
+        }
+        if !matches!(tt, TokenTree::Ident(_)) && !is_punct(&tt, ':') {
+            return false;
+        }
+        path.push(tt);
+    }
+    false
+}
+
+/// Why the visibility of an item couldn't be replaced.
+#[derive(Clone, Debug)]
+pub enum VisibilityError {
+    /// The visibility or an option is malformed.
+    InvalidVisibility {
+        /// What is wrong with the input.
+        message: &'static str,
+        /// The offending input token.
+        span: Span,
+    },
+    /// The item can't take a visibility, or doesn't fit the options.
+    UnsupportedItem {
+        /// What is wrong with the item.
+        message: &'static str,
+        /// The offending item token.
+        span: Span,
+    },
+}
+
+impl VisibilityError {
+    /// The span of the token that caused the error.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::InvalidVisibility { span, .. } | Self::UnsupportedItem { span, .. } => *span,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidVisibility { message, .. } | Self::UnsupportedItem { message, .. } => {
+                message
+            }
+        }
+    }
+
+    /// Builds a `compile_error!` invocation reporting the error at its
+    /// span.
+    ///
+    /// # Examples
+    /// ```
+    /// use visibility_attribute_core::try_set_visibility;
+    ///
+    /// let item: proc_macro2::TokenStream = "fn f() {}".parse().unwrap();
+    /// let error = try_set_visibility("pub(nowhere)".parse().unwrap(), item).unwrap_err();
+    /// assert!(error.into_compile_error().to_string().contains("compile_error"));
+    /// ```
+    pub fn into_compile_error(self) -> TokenStream {
+        compile_error(self.message(), self.span())
+    }
+}
+
+impl core::fmt::Display for VisibilityError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl core::error::Error for VisibilityError {}
+
+/// Keeps the items of `transformed` if the `cfg` predicate holds, and
+/// those of `original` otherwise. Each item gets its own `#[cfg]`.
+fn select_by_cfg(
+    predicate: &TokenStream,
+    transformed: TokenStream,
+    original: TokenStream,
+) -> TokenStream {
+    let cfg = |predicate: TokenStream| -> TokenStream {
+        let arguments = Group::new(Delimiter::Parenthesis, predicate);
+        let attribute = [
+            Ident::new("cfg", Span::call_site()).into(),
+            TokenTree::from(arguments),
+        ];
+        let group = Group::new(Delimiter::Bracket, attribute.into_iter().collect());
+        [
+            Punct::new('#', Spacing::Alone).into(),
+            TokenTree::from(group),
+        ]
+        .into_iter()
+        .collect()
+    };
+    let negated: TokenStream = [
+        Ident::new("not", Span::call_site()).into(),
+        TokenTree::from(Group::new(Delimiter::Parenthesis, predicate.clone())),
+    ]
+    .into_iter()
+    .collect();
+
+    let mut out_stream = TokenStream::new();
+    for item in split_items(transformed) {
+        out_stream.extend(cfg(predicate.clone()));
+        out_stream.extend(item);
+    }
+    for item in split_items(original) {
+        out_stream.extend(cfg(negated.clone()));
+        out_stream.extend(item);
+    }
+    out_stream
+}
+
+/// Builds an item that causes a deprecation warning at `span`.
+///
+/// There is no stable way for a procedural macro to emit a warning, so
+/// this uses a deprecated struct instead.
+fn noop_warning(span: Span) -> TokenStream {
+    let warning: TokenStream = "const _: () = {
+        #[deprecated(note = \"`set_visibility` doesn't change the visibility of this item\")]
+        struct SetVisibilityNoop;
+        let _ = SetVisibilityNoop;
+    };"
+    .parse()
+    .unwrap();
+    respan(warning, span)
+}
+
+/// Replaces the visibility modifier of an item if that would narrow it.
+///
+/// This is the logic behind the `clamp_visibility` attribute. Items
+/// that are already at most as visible as `input`, or whose visibility
+/// can't be compared with it, are returned unchanged.
+pub fn clamp_visibility(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    let input = normalize_visibility(input);
+    if let Err(span) = validate_visibility(input.clone()) {
+        let mut out_stream = compile_error(invalid_visibility_message(&input), span);
+        out_stream.extend(annotated_item);
+        return out_stream;
+    }
+
+    let ceiling: Vec<TokenTree> = input.clone().into_iter().collect();
+    let existing = remove_visibility(annotated_item.clone()).visibility;
+    match compare_visibility(&existing, &ceiling) {
+        Some(Ordering::Greater) => set_visibility(input, annotated_item),
+        _ => annotated_item,
+    }
+}
+
+/// Removes the visibility modifier of an item.
+///
+/// This is the logic behind the `make_private` attribute. Unlike
+/// [`strip_visibility`], it reports a non-empty `input` as an error.
+pub fn make_private(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    if let Err(error) = check_no_argument("`make_private`", input, &annotated_item) {
+        return error;
+    }
+
+    strip_visibility(annotated_item)
+}
+
+/// Replaces the visibility modifier of an item with `pub`.
+///
+/// This is the logic behind the `make_public` attribute. Like
+/// [`make_private`], it reports a non-empty `input` as an error.
+pub fn make_public(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    if let Err(error) = check_no_argument("`make_public`", input, &annotated_item) {
+        return error;
+    }
+
+    let visibility = TokenTree::from(Ident::new("pub", Span::call_site()));
+    set_visibility(visibility.into(), annotated_item)
+}
+
+/// Replaces the visibility modifier of an item with `pub(crate)`.
+///
+/// This is the logic behind the `expose_to_crate` attribute. Like
+/// [`make_private`], it reports a non-empty `input` as an error.
+pub fn expose_to_crate(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    if let Err(error) = check_no_argument("`expose_to_crate`", input, &annotated_item) {
+        return error;
+    }
+
+    set_visibility("pub(crate)".parse().unwrap(), annotated_item)
+}
+
+/// Replaces the visibility modifier of an item with `pub(super)`.
+///
+/// This is the logic behind the `expose_to_super` attribute. Like
+/// [`make_private`], it reports a non-empty `input` as an error.
+pub fn expose_to_super(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    if let Err(error) = check_no_argument("`expose_to_super`", input, &annotated_item) {
+        return error;
+    }
+
+    set_visibility("pub(super)".parse().unwrap(), annotated_item)
+}
+
+/// Picks one of two visibilities depending on a feature of the crate
+/// being compiled.
+///
+/// This is the logic behind the `visibility_for_feature` attribute.
+/// `input` is a feature name string followed by `public = vis` and
+/// `private = vis`. The item is emitted twice, once with each
+/// visibility, behind `#[cfg(feature = "...")]` and its negation, so the
+/// feature is checked where the item is compiled rather than in the
+/// procedural macro.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::visibility_for_feature;
+///
+/// let input = r#""expose", public = pub, private = pub(crate)"#.parse().unwrap();
+/// let out = visibility_for_feature(input, "fn f() {}".parse().unwrap()).to_string();
+/// assert!(out.starts_with(r#"# [cfg (feature = "expose")] pub fn f"#));
+/// assert!(out.contains(r#"# [cfg (not (feature = "expose"))] pub (crate) fn f"#));
+/// ```
+pub fn visibility_for_feature(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    let error = |message: &str, span| {
+        let mut out_stream = compile_error(message, span);
+        out_stream.extend(annotated_item.clone());
+        out_stream
+    };
+
+    let mut args = split_commas(input).into_iter();
+    let feature = match args.next().map(|(arg, _)| arg).as_deref() {
+        Some([TokenTree::Literal(x)]) if unquote(x).is_some() => x.clone(),
+        Some([x, ..]) => return error("expected a feature name string", x.span()),
+        _ => return error("expected a feature name string", Span::call_site()),
+    };
+
+    let mut public = None;
+    let mut private = None;
+    for (arg, _) in args {
+        match &arg[..] {
+            [] => {}
+            [x, TokenTree::Punct(y), rest @ ..] if is_ident(x, "public") && y.as_char() == '=' => {
+                public = Some(rest.iter().cloned().collect::<TokenStream>())
+            }
+            [x, TokenTree::Punct(y), rest @ ..] if is_ident(x, "private") && y.as_char() == '=' => {
+                private = Some(rest.iter().cloned().collect::<TokenStream>())
+            }
+            [x, ..] => return error("unknown `visibility_for_feature` option", x.span()),
+        }
+    }
+    let (Some(public), Some(private)) = (public, private) else {
+        return error(
+            "expected both `public = vis` and `private = vis`",
+            feature.span(),
+        );
+    };
+
+    let rewrite = |visibility| {
+        VisibilityRewriter::new()
+            .with_visibility(visibility)
+            .try_apply(annotated_item.clone())
+    };
+    match (rewrite(public), rewrite(private)) {
+        (Ok(public), Ok(private)) => {
+            let predicate = [
+                Ident::new("feature", Span::call_site()).into(),
+                Punct::new('=', Spacing::Alone).into(),
+                TokenTree::from(feature),
+            ];
+            select_by_cfg(&predicate.into_iter().collect(), public, private)
+        }
+        (Err(x), _) | (_, Err(x)) => error(x.message(), x.span()),
+    }
+}
+
+/// Gives an item a visibility only when compiling tests.
+///
+/// This is the logic behind the `test_visible` attribute. `input` is the
+/// argument `set_visibility` would take. The item is emitted twice: once
+/// rewritten with `input` behind `#[cfg(test)]`, and once made private
+/// with the same options behind `#[cfg(not(test))]`. Only one of the copies is compiled, so
+/// their names don't collide.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::test_visible;
+///
+/// let input = "pub(crate)".parse().unwrap();
+/// let out = test_visible(input, "pub fn f() {}".parse().unwrap()).to_string();
+/// assert_eq!(
+///     out,
+///     "# [cfg (test)] pub (crate) fn f () { } # [cfg (not (test))] fn f () { }"
+/// );
+/// ```
+pub fn test_visible(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    let error = |message: &str, span| {
+        let mut out_stream = compile_error(message, span);
+        out_stream.extend(annotated_item.clone());
+        out_stream
+    };
+
+    if input.is_empty() {
+        return error("expected the visibility to use in tests", Span::call_site());
+    }
+    let rewriter = match parse_arguments(input) {
+        Ok(x) => x,
+        Err((message, span)) => return error(message, span),
+    };
+    // The private copy keeps the options, so `allow_dead` still applies
+    // where the item may be unused.
+    let private = rewriter.clone().with_visibility(TokenStream::new());
+    match (
+        rewriter.try_apply(annotated_item.clone()),
+        private.try_apply(annotated_item.clone()),
+    ) {
+        (Ok(visible), Ok(private)) => {
+            let predicate = Ident::new("test", Span::call_site());
+            select_by_cfg(&TokenTree::from(predicate).into(), visible, private)
+        }
+        (Err(x), _) | (_, Err(x)) => error(x.message(), x.span()),
+    }
+}
+
+/// Replaces a `from_env = "NAME"` option of `set_visibility` with the
+/// visibility held by the environment variable `NAME`.
+///
+/// This crate can't read the environment itself, so `lookup` gets the
+/// value of a variable, or `None` if it isn't set. A value is passed on
+/// as a string literal with the span of `"NAME"`, and checked like any
+/// other visibility. An unset variable leaves no visibility, making the
+/// item private.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::{resolve_from_env, set_visibility};
+///
+/// let input = r#"from_env = "VIS""#.parse().unwrap();
+/// let input = resolve_from_env(input, |_| Some("pub(crate)".to_owned()));
+/// let item = "fn f() {}".parse().unwrap();
+/// assert_eq!(set_visibility(input, item).to_string(), "pub (crate) fn f () { }");
+/// ```
+pub fn resolve_from_env(
+    input: TokenStream,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> TokenStream {
+    let mut out = TokenStream::new();
+    for (arg, comma) in split_commas(input) {
+        match &arg[..] {
+            [x, TokenTree::Punct(y), TokenTree::Literal(z)]
+                if is_ident(x, "from_env") && y.as_char() == '=' && unquote(z).is_some() =>
+            {
+                if let Some(value) = lookup(&unquote(z).unwrap()) {
+                    let mut literal = Literal::string(&value);
+                    literal.set_span(z.span());
+                    out.extend([TokenTree::from(literal)]);
+                }
+            }
+            _ => out.extend(arg),
+        }
+        out.extend(comma);
+    }
+    out
+}
+
+/// Reports an `input` given to an attribute that doesn't take one.
+///
+/// The error is a `compile_error!` invocation followed by the unchanged
+/// item.
+fn check_no_argument(
+    attribute: &str,
+    input: TokenStream,
+    annotated_item: &TokenStream,
+) -> Result<(), TokenStream> {
+    match input.into_iter().next() {
+        Some(tt) => {
+            let message = format!("{attribute} doesn't take an argument");
+            let mut out_stream = compile_error(&message, tt.span());
+            out_stream.extend(annotated_item.clone());
+            Err(out_stream)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Replaces the visibility modifier of every item in a block.
+///
+/// This is the logic behind the `set_visibility_block!` macro. `input`
+/// starts with the same argument `set_visibility` takes, followed by a
+/// `;` and then the items. The associated items of inherent `impl`
+/// blocks are rewritten too, while trait `impl`s, whose items can't have
+/// a visibility, are reported as an error.
+/// Macro invocations are left as they are.
+///
+/// The argument can also have a `depth = n` option, to rewrite the items
+/// inside brace groups that aren't items themselves, such as the body of
+/// a macro invocation or a branch of `cfg_if!`. Each group nested in
+/// another counts as one more level, so the items in the branches of a
+/// `cfg_if!` invocation are two levels down. Groups past the depth are
+/// left alone, and the default depth is 0.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::set_visibility_block;
+///
+/// let input = "pub(crate); fn f() {} pub struct S;".parse().unwrap();
+/// assert_eq!(
+///     set_visibility_block(input).to_string(),
+///     "pub (crate) fn f () { } pub (crate) struct S ;"
+/// );
+///
+/// let input = "pub, depth = 1; wrap! { fn f() {} }".parse().unwrap();
+/// assert_eq!(
+///     set_visibility_block(input).to_string(),
+///     "wrap ! { pub fn f () { } }"
+/// );
+/// ```
+pub fn set_visibility_block(input: TokenStream) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = input.into_iter().collect();
+    let Some(end) = tokens.iter().position(|x| is_punct(x, ';')) else {
+        return match tokens.last() {
+            Some(x) => compile_error("expected `;` after the visibility", x.span()),
+            None => TokenStream::new(),
+        };
+    };
+    let items = tokens.split_off(end + 1);
+    let semicolon = tokens.pop().unwrap();
+    let args: TokenStream = tokens.into_iter().collect();
+
+    if items.is_empty() {
+        return compile_error("no items to apply visibility to", semicolon.span());
+    }
+
+    match take_depth(args) {
+        Ok((args, depth)) => set_block_item_visibility(&args, items, depth),
+        Err(span) => compile_error("expected a nesting depth", span),
+    }
+}
+
+/// Splits a `depth = n` option off of the argument of
+/// `set_visibility_block!`, returning the rest of the argument and `n`.
+fn take_depth(args: TokenStream) -> Result<(TokenStream, usize), Span> {
+    let mut rest = TokenStream::new();
+    let mut depth = 0;
+    for (arg, comma) in split_commas(args) {
+        match &arg[..] {
+            [x, TokenTree::Punct(y), value @ ..] if is_ident(x, "depth") && y.as_char() == '=' => {
+                depth = match value {
+                    [TokenTree::Literal(z)] => z.to_string().parse().map_err(|_| z.span())?,
+                    [z, ..] => return Err(z.span()),
+                    [] => return Err(y.span()),
+                };
+            }
+            _ => {
+                rest.extend(arg);
+                rest.extend(comma);
+            }
+        }
+    }
+    Ok((rest, depth))
+}
+
+/// Rewrites the items of a block, descending `depth` levels into brace
+/// groups that aren't items.
+fn set_block_item_visibility(
+    args: &TokenStream,
+    items: impl IntoIterator<Item = TokenTree>,
+    depth: usize,
+) -> TokenStream {
+    split_items(items)
+        .into_iter()
+        .flat_map(|mut item| {
+            if let Some(i) = nested_block(&item) {
+                // Past the depth, groups and the `if`/`else` of `cfg_if!`
+                // branches aren't items, so they are left as they are.
+                if depth == 0 {
+                    return item.into_iter().collect();
+                }
+                let TokenTree::Group(group) = &item[i] else {
+                    unreachable!()
+                };
+                let stream = set_block_item_visibility(args, group.stream(), depth - 1);
+                let mut new_group = Group::new(group.delimiter(), stream);
+                new_group.set_span(group.span());
+                item[i] = new_group.into();
+                return item.into_iter().collect();
+            }
+            if is_macro_invocation(&item) {
+                return item.into_iter().collect();
+            }
+            match impl_body(&item) {
+                Some(Ok(body)) => set_impl_item_visibility(args.clone(), item, body),
+                Some(Err(span)) => {
+                    let mut out_stream = compile_error(
+                        "items of trait `impl`s can't have a visibility; \
+                        `set_visibility_block!` only rewrites inherent `impl`s",
+                        span,
+                    );
+                    out_stream.extend(item);
+                    out_stream
+                }
+                None => set_visibility(args.clone(), item.into_iter().collect()),
+            }
+        })
+        .collect()
+}
+
+/// Finds the brace group ending an entry of a block that isn't an item:
+/// a group on its own, the body of a macro invocation, or a branch of
+/// `cfg_if!`.
+fn nested_block(item: &[TokenTree]) -> Option<usize> {
+    let (TokenTree::Group(group), rest) = item.split_last()? else {
+        return None;
+    };
+    if group.delimiter() != Delimiter::Brace {
+        return None;
+    }
+    let is_branch = rest
+        .first()
+        .is_some_and(|x| is_ident(x, "if") || is_ident(x, "else"));
+    let is_macro = rest.last().is_some_and(|x| is_punct(x, '!'));
+    (rest.is_empty() || is_branch || is_macro).then_some(rest.len())
+}
+
+/// Removes the visibility modifier of every item in a block.
+///
+/// This is the logic behind the `strip_all_visibility!` macro. If
+/// `input` starts with `fields;`, the fields of structs and the
+/// declarations of `extern` blocks are made private too. Only top-level
+/// items are changed, so the associated items of `impl` blocks and the
+/// items inside modules keep their visibility.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::strip_all_visibility;
+///
+/// let input = "fields; pub fn f() {} pub struct S { pub a: u8 }".parse().unwrap();
+/// assert_eq!(
+///     strip_all_visibility(input).to_string(),
+///     "fn f () { } struct S { a : u8 }"
+/// );
+/// ```
+pub fn strip_all_visibility(input: TokenStream) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = input.into_iter().collect();
+    let fields = match &tokens[..] {
+        [x, TokenTree::Punct(y), ..] => is_ident(x, "fields") && y.as_char() == ';',
+        _ => false,
+    };
+    if fields {
+        tokens.drain(..2);
+    }
+
+    split_items(tokens)
+        .into_iter()
+        .flat_map(|item| {
+            let item = strip_visibility(item.into_iter().collect());
+            if fields {
+                // Anything without fields is left as it is.
+                set_field_visibility(TokenStream::new(), item.clone(), None).unwrap_or(item)
+            } else {
+                item
+            }
+        })
+        .collect()
+}
+
+/// Finds the index of the body of an `impl` block.
+///
+/// If the block implements a trait, the span of the `for` is returned
+/// as an error instead. Anything other than an `impl` block gives `None`.
+fn impl_body(item: &[TokenTree]) -> Option<Result<usize, Span>> {
+    let mut stripped = remove_visibility(item.iter().cloned());
+    if !stripped.item.next().is_some_and(|x| is_ident(&x, "impl")) {
+        return None;
+    }
+
+    let mut angle_depth = 0usize;
+    for (i, tt) in item.iter().enumerate() {
+        // `for<'a>` is a higher-ranked bound rather than a trait impl.
+        let is_higher_ranked =
+            || matches!(item.get(i + 1), Some(TokenTree::Punct(x)) if x.as_char() == '<');
+        match tt {
+            TokenTree::Punct(x) if x.as_char() == '<' => angle_depth += 1,
+            TokenTree::Punct(x) if x.as_char() == '>' && !is_arrow_head(&item[i - 1]) => {
+                angle_depth = angle_depth.saturating_sub(1)
+            }
+            TokenTree::Ident(x) if angle_depth == 0 && *x == "for" && !is_higher_ranked() => {
+                return Some(Err(x.span()))
+            }
+            TokenTree::Group(x) if angle_depth == 0 && x.delimiter() == Delimiter::Brace => {
+                return Some(Ok(i))
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replaces the visibility modifier of every associated item of an
+/// inherent `impl` block, given the index of its body.
+fn set_impl_item_visibility(
+    args: TokenStream,
+    mut item: Vec<TokenTree>,
+    body: usize,
+) -> TokenStream {
+    let TokenTree::Group(group) = &item[body] else {
+        unreachable!()
+    };
+    // Associated types only take a visibility on nightly, so they are
+    // left alone, as are macro invocations.
+    let is_type = |x: &[TokenTree]| {
+        let mut stripped = remove_visibility(x.iter().cloned());
+        stripped.item.next().is_some_and(|y| is_ident(&y, "type"))
+    };
+    let associated_items: TokenStream = split_items(group.stream())
+        .into_iter()
+        .flat_map(|x| {
+            if is_type(&x) || is_macro_invocation(&x) {
+                x.into_iter().collect()
+            } else {
+                set_visibility(args.clone(), x.into_iter().collect())
+            }
+        })
+        .collect();
+    let mut new_group = Group::new(Delimiter::Brace, associated_items);
+    new_group.set_span(group.span());
+    item[body] = new_group.into();
+    item.into_iter().collect()
+}
+
+/// Splits a sequence of items into the tokens of each item.
+///
+/// An item ends with a top-level `;` or, unless it is a `use` or has an
+/// `=` before it, with a top-level brace group. After a top-level `=`,
+/// `<` and `>` are likely comparisons or shifts, so they are no longer
+/// counted as brackets.
+fn split_items(tokens: impl IntoIterator<Item = TokenTree>) -> Vec<Vec<TokenTree>> {
+    fn is_use(item: &[TokenTree]) -> bool {
+        let mut stripped = remove_visibility(item.iter().cloned());
+        stripped.item.next().is_some_and(|x| is_ident(&x, "use"))
+    }
+
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut angle_depth = 0usize;
+    let mut has_equals = false;
+
+    for tt in tokens {
+        let ends_item = match &tt {
+            TokenTree::Punct(x) => match x.as_char() {
+                _ if has_equals => x.as_char() == ';',
+                '<' => {
+                    angle_depth += 1;
+                    false
+                }
+                '>' if !item.last().is_some_and(is_arrow_head) => {
+                    angle_depth = angle_depth.saturating_sub(1);
+                    false
+                }
+                '=' if angle_depth == 0 => {
+                    has_equals = true;
+                    false
+                }
+                ';' => angle_depth == 0,
+                _ => false,
+            },
+            TokenTree::Group(x) => {
+                x.delimiter() == Delimiter::Brace
+                    && angle_depth == 0
+                    && !has_equals
+                    && !is_use(&item)
+            }
+            _ => false,
+        };
+
+        item.push(tt);
+        if ends_item {
+            items.push(core::mem::take(&mut item));
+            angle_depth = 0;
+            has_equals = false;
+        }
+    }
+
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+/// Lexes the contents of a visibility given as a string literal.
+///
+/// `"pub(crate)"` becomes `pub(crate)`, with every token spanned to the
+/// literal. Anything other than a single string literal is returned as
+/// it is.
+fn unquote_visibility(visibility: TokenStream) -> Result<TokenStream, Span> {
+    let tokens: Vec<TokenTree> = visibility.clone().into_iter().collect();
+    let [TokenTree::Literal(x)] = &tokens[..] else {
+        return Ok(visibility);
+    };
+    if !x.to_string().starts_with('"') {
+        return Ok(visibility);
+    }
+    let Some(contents) = unquote(x) else {
+        return Err(x.span());
+    };
+    match contents.parse() {
+        Ok(y) => Ok(respan(y, x.span())),
+        Err(_) => Err(x.span()),
+    }
+}
+
+/// Gets the contents of a plain string literal.
+///
+/// Other literals, and strings with escapes, give `None`.
+fn unquote(literal: &Literal) -> Option<String> {
+    let text = literal.to_string();
+    let contents = text.strip_prefix('"')?.strip_suffix('"')?;
+    (!contents.contains('\\')).then(|| contents.to_owned())
+}
+
+/// Rewrites a visibility into its simplest equivalent form.
+///
+/// `pub(self)` is the same as private, so it becomes nothing. The bare
+/// `crate` visibility from before the 2018 edition becomes `pub(crate)`.
+/// The presets `internal` and `public` become `pub(crate)` and `pub`.
+fn normalize_visibility(visibility: TokenStream) -> TokenStream {
+    let pub_crate = |span| {
+        let mut group = Group::new(
+            Delimiter::Parenthesis,
+            TokenTree::from(Ident::new("crate", span)).into(),
+        );
+        group.set_span(span);
+        [Ident::new("pub", span).into(), TokenTree::from(group)]
+            .into_iter()
+            .collect()
+    };
+
+    let tokens: Vec<TokenTree> = visibility.clone().into_iter().collect();
+    match &tokens[..] {
+        [x, y] if is_ident(x, "pub") && is_restricted_to(y, "self") => TokenStream::new(),
+        [x] if is_ident(x, "crate") || is_ident(x, "internal") => pub_crate(x.span()),
+        [x] if is_ident(x, "public") => TokenTree::from(Ident::new("pub", x.span())).into(),
+        _ => visibility,
+    }
+}
+
+/// Checks for the parentheses of `pub(name)`.
+fn is_restricted_to(tt: &TokenTree, name: &str) -> bool {
+    let TokenTree::Group(group) = tt else {
+        return false;
+    };
+    let mut tt_iter = group.stream().into_iter();
+    group.delimiter() == Delimiter::Parenthesis
+        && matches!((tt_iter.next(), tt_iter.next()), (Some(x), None) if is_ident(&x, name))
+}
+
+/// Replaces the visibility of an item or field with a valid visibility.
+///
+/// The visibility is spliced in after the attributes, so only the
+/// tokens before the item's keyword are moved. The removed visibility is
+/// returned.
+fn replace_visibility(
+    visibility: TokenStream,
+    tokens: &mut Vec<TokenTree>,
+    tuple_field: bool,
+) -> Vec<TokenTree> {
+    let Stripped {
+        attributes,
+        visibility: removed,
+        ..
+    } = remove_visibility_of(tokens.iter().cloned(), tuple_field);
+    let start = attributes.len();
+
+    let visibility = match removed.first() {
+        Some(x) => respan(visibility, x.span()),
+        None => visibility,
+    };
+    tokens.splice(start..start + removed.len(), visibility);
+    removed
+}
+
+/// Sets the visibility of every field of a struct or union, or of every
+/// declaration in an `extern` block.
+///
+/// If `only` is given, just that field of the struct is changed. The
+/// struct's own visibility is left alone.
+fn set_field_visibility(
+    visibility: TokenStream,
+    item: TokenStream,
+    only: Option<FieldSelector>,
+) -> Result<TokenStream, (&'static str, Span)> {
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    if let Some(body) = extern_block_body(&tokens).filter(|_| only.is_none()) {
+        let TokenTree::Group(group) = &tokens[body] else {
+            unreachable!()
+        };
+        let declarations: TokenStream = split_items(group.stream())
+            .into_iter()
+            .flat_map(|mut x| {
+                replace_visibility(visibility.clone(), &mut x, false);
+                x
+            })
+            .collect();
+        let mut new_group = Group::new(Delimiter::Brace, declarations);
+        new_group.set_span(group.span());
+        tokens[body] = new_group.into();
+        return Ok(tokens.into_iter().collect());
+    }
+
+    // Union fields are just like the named fields of a struct.
+    let stripped = remove_visibility(tokens.clone());
+    let keyword = stripped.attributes.len() + stripped.visibility.len();
+    match &tokens[keyword..] {
+        [x, ..] if is_ident(x, "struct") => {}
+        [x, TokenTree::Ident(_), ..] if is_ident(x, "union") => {}
+        _ => {
+            return Err((
+                "`fields` can only be used on a struct, a union or an `extern` block",
+                tokens
+                    .get(keyword)
+                    .map_or_else(Span::call_site, TokenTree::span),
+            ))
+        }
+    }
+
+    // Generics can contain parentheses, as in `F: Fn(u8)`, and so can
+    // a where clause. Tuple structs have theirs after the body.
+    let mut angle_depth = 0usize;
+    let mut after_where = false;
+    let mut body = None;
+    for (i, tt) in tokens.iter().enumerate().skip(keyword + 1) {
+        match tt {
+            TokenTree::Punct(x) if x.as_char() == '<' => angle_depth += 1,
+            TokenTree::Punct(x) if x.as_char() == '>' && !is_arrow_head(&tokens[i - 1]) => {
+                angle_depth = angle_depth.saturating_sub(1)
+            }
+            TokenTree::Ident(x) if angle_depth == 0 && *x == "where" => after_where = true,
+            TokenTree::Group(x) if angle_depth == 0 => match x.delimiter() {
+                Delimiter::Brace => {
+                    body = Some(i);
+                    break;
+                }
+                Delimiter::Parenthesis if !after_where => {
+                    body = Some(i);
+                    break;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // Unit structs have no fields to change.
+    let Some(body) = body else {
+        return match only {
+            Some(x) => Err((x.not_found_message(), tokens[keyword].span())),
+            None => Ok(tokens.into_iter().collect()),
+        };
+    };
+    let TokenTree::Group(group) = &tokens[body] else {
+        unreachable!()
+    };
+    match (only, group.delimiter()) {
+        (Some(FieldSelector::Name(_)), Delimiter::Parenthesis) => {
+            return Err((
+                "`field` can only be used on a struct with named fields",
+                group.span(),
+            ))
+        }
+        (Some(FieldSelector::Index(_)), Delimiter::Brace) => {
+            return Err((
+                "`index` can only be used on a tuple struct; use `field` instead",
+                group.span(),
+            ))
+        }
+        _ => {}
+    }
+
+    let mut fields = TokenStream::new();
+    let mut found = false;
+    for (i, (field, comma)) in split_commas(group.stream()).into_iter().enumerate() {
+        let is_selected = |selector| match selector {
+            FieldSelector::Name(name) => {
+                let mut stripped = remove_visibility(field.clone());
+                stripped.item.next().is_some_and(|x| is_ident(&x, name))
+            }
+            FieldSelector::Index(index) => i == index,
+        };
+        if only.is_some_and(|x| !is_selected(x)) {
+            fields.extend(field);
+        } else if !field.is_empty() {
+            found = true;
+            let mut field = field;
+            let tuple_field = group.delimiter() == Delimiter::Parenthesis;
+            replace_visibility(visibility.clone(), &mut field, tuple_field);
+            fields.extend(field);
+        }
+        fields.extend(comma);
+    }
+    if let Some(x) = only.filter(|_| !found) {
+        return Err((x.not_found_message(), group.span()));
+    }
+
+    let mut new_group = Group::new(group.delimiter(), fields);
+    new_group.set_span(group.span());
+    tokens[body] = new_group.into();
+    Ok(tokens.into_iter().collect())
+}
+
+/// The single field of a struct that [`set_field_visibility`] rewrites.
+#[derive(Clone, Copy)]
+enum FieldSelector<'a> {
+    /// A named field, from the `field` option.
+    Name(&'a str),
+    /// A field of a tuple struct, from the `index` option.
+    Index(usize),
+}
+
+impl FieldSelector<'_> {
+    fn not_found_message(self) -> &'static str {
+        match self {
+            Self::Name(_) => "`field` doesn't name a field of this struct",
+            Self::Index(_) => "`index` is out of range for this struct",
+        }
+    }
+}
+
+/// Finds the index of the braces of an `extern` block, as in
+/// `extern "C" { ... }` or, since the 2024 edition, `unsafe extern "C"
+/// { ... }`.
+fn extern_block_body(tokens: &[TokenTree]) -> Option<usize> {
+    let mut stripped = remove_visibility(tokens.iter().cloned());
+    let mut offset = stripped.attributes.len() + stripped.visibility.len();
+    let mut item = stripped.item.by_ref().peekable();
+    if item.next_if(|x| is_ident(x, "unsafe")).is_some() {
+        offset += 1;
+    }
+    match (item.next(), item.next(), item.next()) {
+        (Some(x), Some(TokenTree::Group(y)), _)
+            if is_ident(&x, "extern") && y.delimiter() == Delimiter::Brace =>
+        {
+            Some(offset + 1)
+        }
+        (Some(x), Some(TokenTree::Literal(_)), Some(TokenTree::Group(y)))
+            if is_ident(&x, "extern") && y.delimiter() == Delimiter::Brace =>
+        {
+            Some(offset + 2)
+        }
+        _ => None,
+    }
+}
+
+/// Gives every token in the stream, including nested ones, the span.
+fn respan(stream: TokenStream, span: Span) -> TokenStream {
+    stream
+        .into_iter()
+        .map(|mut tt| {
+            if let TokenTree::Group(x) = &tt {
+                tt = Group::new(x.delimiter(), respan(x.stream(), span)).into();
+            }
+            tt.set_span(span);
+            tt
+        })
+        .collect()
+}
+
+/// Splits a token stream at its top-level commas.
+///
+/// Commas between angle brackets, as in `HashMap<K, V>`, don't count.
+/// Each part is returned along with the comma that ended it.
+fn split_commas(input: TokenStream) -> Vec<(Vec<TokenTree>, Option<TokenTree>)> {
+    let mut parts = Vec::new();
+    let mut part = Vec::new();
+    let mut angle_depth = 0usize;
+
+    for tt in input {
+        if let TokenTree::Punct(x) = &tt {
+            match x.as_char() {
+                '<' => angle_depth += 1,
+                '>' if !part.last().is_some_and(is_arrow_head) => {
+                    angle_depth = angle_depth.saturating_sub(1)
+                }
+                ',' if angle_depth == 0 => {
+                    parts.push((core::mem::take(&mut part), Some(tt)));
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        part.push(tt);
+    }
+
+    if !part.is_empty() || parts.is_empty() {
+        parts.push((part, None));
+    }
+    parts
+}
+
+/// Checks for the `-` of a `->`, which means the next `>` isn't a bracket.
+fn is_arrow_head(tt: &TokenTree) -> bool {
+    match tt {
+        TokenTree::Punct(x) => x.as_char() == '-' && x.spacing() == Spacing::Joint,
+        _ => false,
+    }
+}
+
+/// Checks for an identifier or keyword.
+///
+/// Raw identifiers keep their `r#` prefix when rendered, so `r#pub`
+/// never matches the `pub` keyword.
+fn is_ident(tt: &TokenTree, name: &str) -> bool {
+    match tt {
+        TokenTree::Ident(x) => x == name,
+        _ => false,
+    }
+}
+
+/// Checks for a punct of the character, whatever its spacing.
+fn is_punct(tt: &TokenTree, ch: char) -> bool {
+    match tt {
+        TokenTree::Punct(x) => x.as_char() == ch,
+        _ => false,
+    }
+}
+
+/// Checks for a group in parentheses.
+fn is_paren_group(tt: &TokenTree) -> bool {
+    match tt {
+        TokenTree::Group(x) => x.delimiter() == Delimiter::Parenthesis,
+        _ => false,
+    }
+}
+
+/// An item split around its visibility modifier by [`remove_visibility`].
+struct Stripped<I> {
+    /// The outer attributes before the visibility.
+    attributes: Vec<TokenTree>,
+    /// The removed visibility, which is empty if the item was private.
+    visibility: Vec<TokenTree>,
+    /// The rest of the item after the visibility.
+    item: I,
+}
+
+impl<I: Iterator<Item = TokenTree>> Stripped<I> {
+    /// The item without its visibility, including its attributes.
+    fn rest(self) -> impl Iterator<Item = TokenTree> {
+        self.attributes.into_iter().chain(self.item)
+    }
+}
+
+/// Removes the visibility modifier from a TokenTree iterable.
+///
+/// Any outer attributes before the visibility are kept in place.
+/// It returns the parts of the item as iterators and vectors rather
+/// than TokenStreams. All tokens keep their spans.
+fn remove_visibility(
+    input: impl IntoIterator<Item = TokenTree>,
+) -> Stripped<impl Iterator<Item = TokenTree>> {
+    remove_visibility_of(input, false)
+}
+
+/// Removes the visibility modifier from an item or a tuple struct field.
+///
+/// For a tuple field, the parentheses after `pub` are only taken as part
+/// of the visibility if they look like a restriction, as `pub (u8, u8)`
+/// is a public field of a tuple type.
+fn remove_visibility_of(
+    input: impl IntoIterator<Item = TokenTree>,
+    tuple_field: bool,
+) -> Stripped<impl Iterator<Item = TokenTree>> {
+    /// Checks for the contents of `pub(crate)`, `pub(self)`, `pub(super)`
+    /// and `pub(in path)`, the same way rustc does.
+    fn is_restriction(tt: &TokenTree) -> bool {
+        let TokenTree::Group(group) = tt else {
+            return false;
+        };
+        let mut tt_iter = group.stream().into_iter();
+        match (tt_iter.next(), tt_iter.next()) {
+            (Some(x), None) => ["crate", "self", "super"].iter().any(|kw| is_ident(&x, kw)),
+            (Some(x), Some(_)) => is_ident(&x, "in"),
+            _ => false,
+        }
+    }
+
+    fn skip_visibility(
+        tt_iter: &mut Peekable<impl Iterator<Item = TokenTree>>,
+        removed: &mut Vec<TokenTree>,
+        tuple_field: bool,
+    ) {
+        match tt_iter.next_if(|x| is_ident(x, "pub")) {
+            Some(x) => removed.push(x),
+            None => return,
+        }
+
+        // `pub(crate)`, `pub(in path)`, etc.
+        if let Some(x) =
+            tt_iter.next_if(|x| is_paren_group(x) && (!tuple_field || is_restriction(x)))
+        {
+            removed.push(x);
+            return;
+        }
+
+        // `pub in path` is what is left when the parentheses of
+        // `pub(in path)` have been lost. The path is stripped with it.
+        if let Some(x) = tt_iter.next_if(|x| is_ident(x, "in")) {
+            removed.push(x);
+            while let Some(x) = tt_iter.next_if(|x| matches!(x, TokenTree::Ident(_))) {
+                removed.push(x);
+                match tt_iter.next_if(|x| match x {
+                    TokenTree::Punct(y) => y.as_char() == ':' && y.spacing() == Spacing::Joint,
+                    _ => false,
+                }) {
+                    Some(x) => removed.push(x),
+                    None => break,
+                }
+                removed.extend(tt_iter.next_if(|x| is_punct(x, ':')));
+            }
+        }
+    }
+
+    let mut tt_iter = input.into_iter().peekable();
+    let mut attributes = Vec::new();
+    let mut visibility = Vec::new();
+
+    if take_attributes(&mut tt_iter, &mut attributes) {
+        skip_visibility(&mut tt_iter, &mut visibility, tuple_field);
+    }
+    Stripped {
+        attributes,
+        visibility,
+        item: tt_iter,
+    }
+}
+
+/// Takes the outer attributes from the front of an iterator.
+///
+/// Outer attributes are a `#` followed by a bracketed group. If a `#`
+/// isn't followed by one, it is taken anyway and false is returned.
+fn take_attributes(
+    tt_iter: &mut Peekable<impl Iterator<Item = TokenTree>>,
+    attributes: &mut Vec<TokenTree>,
+) -> bool {
+    while let Some(pound) = tt_iter.next_if(|x| is_punct(x, '#')) {
+        attributes.push(pound);
+        match tt_iter.next_if(|x| match x {
+            TokenTree::Group(y) => y.delimiter() == Delimiter::Bracket,
+            _ => false,
+        }) {
+            Some(group) => attributes.push(group),
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Checks that an item can take a visibility.
+fn check_item_kind(item: &[TokenTree]) -> Result<(), (&'static str, Span)> {
+    /// Keywords that start something other than an item, or an item
+    /// without a visibility.
+    const NO_VISIBILITY: [&str; 8] = [
+        "for", "while", "loop", "if", "match", "return", "break", "continue",
+    ];
+    const IMPL_MESSAGE: &str = "`impl` blocks don't take a visibility; \
+        apply `set_visibility` to the associated items instead";
+
+    let Stripped {
+        attributes,
+        visibility,
+        item: mut tt_iter,
+    } = remove_visibility(item.iter().cloned());
+    let is_macro = is_macro_invocation(item);
+    let is_extern_block = extern_block_body(item).is_some();
+    // The attributes only end in a `#` if it isn't followed by `[..]`.
+    if let Some(pound) = attributes.last().filter(|x| is_punct(x, '#')) {
+        return match tt_iter.next() {
+            Some(x) if is_punct(&x, '!') => Err((
+                "inner attributes like `#![..]` can't be on an item; use `#[..]` instead",
+                pound.span(),
+            )),
+            _ => Err(("expected `[` after `#`", pound.span())),
+        };
+    }
+    match tt_iter.next() {
+        None => {
+            let span = attributes
+                .iter()
+                .chain(&visibility)
+                .next_back()
+                .map(TokenTree::span);
+            let span = span.unwrap_or_else(Span::call_site);
+            if attributes.is_empty() {
+                Err(("expected an item", span))
+            } else {
+                Err(("expected an item after attributes", span))
+            }
+        }
+        Some(x)
+            if is_ident(&x, "macro_rules")
+                && matches!(tt_iter.next(), Some(TokenTree::Punct(y)) if y.as_char() == '!') =>
+        {
+            Err((
+                "`macro_rules!` macros don't have a visibility; use `#[macro_export]` instead",
+                x.span(),
+            ))
+        }
+        Some(x) if is_extern_block => Err((
+            "`extern` blocks don't take a visibility; \
+            use the `fields` option to set the visibility of their declarations instead",
+            x.span(),
+        )),
+        // Before the check for macros, which `impl !Send for ..` would pass.
+        Some(x) if is_ident(&x, "impl") => Err((IMPL_MESSAGE, x.span())),
+        Some(x) if is_ident(&x, "unsafe") => match tt_iter.next() {
+            Some(y) if is_ident(&y, "impl") => Err((IMPL_MESSAGE, y.span())),
+            _ => Ok(()),
+        },
+        Some(x) if is_macro => Err((
+            "macro invocations don't take a visibility; \
+            apply `set_visibility` to the items they expand to instead",
+            x.span(),
+        )),
+        // Likely a bug in whatever generated the item.
+        Some(x) if is_ident(&x, "pub") => Err(("the item has more than one visibility", x.span())),
+        // `default pub fn` is how `pub default fn` is easily misremembered.
+        Some(x) if is_ident(&x, "default") => match tt_iter.next() {
+            Some(y) if is_ident(&y, "pub") => {
+                Err(("the visibility goes before `default`", y.span()))
+            }
+            _ => Ok(()),
+        },
+        // The most common mistake, so it gets its own message.
+        Some(x) if is_ident(&x, "let") => Err((
+            "local bindings can't have a visibility; `set_visibility` only applies to items",
+            x.span(),
+        )),
+        Some(x) if NO_VISIBILITY.iter().any(|kw| is_ident(&x, kw)) => Err((
+            "`set_visibility` only applies to items that accept a visibility modifier",
+            x.span(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a TokenTree iterable is a single visibility modifier.
+///
+/// Nothing, `pub`, `pub(crate)`, `pub(self)`, `pub(super)` and
+/// `pub(in path)` are accepted. Otherwise, the span of the first
+/// offending token is returned.
+fn validate_visibility(input: impl IntoIterator<Item = TokenTree>) -> Result<(), Span> {
+    fn is_path_sep(first: &TokenTree, second: &TokenTree) -> bool {
+        match (first, second) {
+            (TokenTree::Punct(x), TokenTree::Punct(y)) => {
+                x.as_char() == ':' && x.spacing() == Spacing::Joint && y.as_char() == ':'
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks the contents of the parentheses in `pub(...)`.
+    fn check_restriction(group: &Group) -> Result<(), Span> {
+        let tokens: Vec<TokenTree> = group.stream().into_iter().collect();
+        match tokens.as_slice() {
+            [x] if ["crate", "self", "super"]
+                .iter()
+                .any(|name| is_ident(x, name)) =>
+            {
+                Ok(())
+            }
+            [x, path @ ..] if is_ident(x, "in") => {
+                let mut rest = path;
+                loop {
+                    match rest {
+                        [TokenTree::Ident(_)] => return Ok(()),
+                        [TokenTree::Ident(_), a, b, tail @ ..] if is_path_sep(a, b) => rest = tail,
+                        [TokenTree::Ident(_), x, ..] | [x, ..] => return Err(x.span()),
+                        [] => return Err(group.span()),
+                    }
+                }
+            }
+            [x, ..] => Err(x.span()),
+            [] => Err(group.span()),
+        }
+    }
+
+    let mut tt_iter = input.into_iter();
+
+    match tt_iter.next() {
+        None => return Ok(()),
+        Some(x) if is_ident(&x, "pub") => {}
+        Some(x) => return Err(x.span()),
+    }
+
+    match tt_iter.next() {
+        None => return Ok(()),
+        Some(TokenTree::Group(x)) if x.delimiter() == Delimiter::Parenthesis => {
+            check_restriction(&x)?
+        }
+        Some(x) => return Err(x.span()),
+    }
+
+    match tt_iter.next() {
+        None => Ok(()),
+        Some(x) => Err(x.span()),
+    }
+}
+
+/// Explains why [`validate_visibility`] rejected a visibility.
+fn invalid_visibility_message(visibility: &TokenStream) -> &'static str {
+    /// Checks for a path of more than one segment, such as `crate::a`.
+    fn is_path(stream: TokenStream) -> bool {
+        let tokens: Vec<TokenTree> = stream.into_iter().collect();
+        tokens.len() > 1
+            && tokens.iter().enumerate().all(|(i, x)| match (i % 3, x) {
+                (0, TokenTree::Ident(_)) => true,
+                (1, TokenTree::Punct(y)) => y.as_char() == ':' && y.spacing() == Spacing::Joint,
+                (2, TokenTree::Punct(y)) => y.as_char() == ':',
+                _ => false,
+            })
+            && tokens.len() % 3 == 1
+    }
+
+    let tokens: Vec<TokenTree> = visibility.clone().into_iter().collect();
+    // A whole visibility followed by something else, such as an option
+    // missing its comma.
+    let mut stripped = remove_visibility(tokens.clone());
+    let is_followed = stripped.item.next().is_some()
+        && !stripped.visibility.is_empty()
+        && validate_visibility(stripped.visibility).is_ok();
+
+    match &tokens[..] {
+        [x, TokenTree::Group(y)]
+            if is_ident(x, "pub")
+                && y.delimiter() == Delimiter::Parenthesis
+                && y.stream().is_empty() =>
+        {
+            "`pub()` isn't a visibility; use `pub(crate)` or `pub` instead"
+        }
+        [x, TokenTree::Group(y)]
+            if is_ident(x, "pub")
+                && y.delimiter() == Delimiter::Parenthesis
+                && is_path(y.stream()) =>
+        {
+            "a path in a visibility needs `in`, as in `pub(in super::super)`"
+        }
+        _ if is_followed => "unexpected token after the visibility; separate options with commas",
+        _ => "expected a visibility such as `pub` or `pub(crate)`",
+    }
+}
+
+/// How widely visible an item is.
+///
+/// Visibilities are only partially ordered by how widely visible they
+/// are, so this implements [`PartialOrd`] but not [`Ord`]. `pub(in path)`
+/// is wider than private and narrower than `pub(crate)`, and it is wider
+/// than the paths to the modules inside `path`. A path of only `super`s
+/// is also wider than the paths with fewer `super`s. Other paths can't be
+/// compared, as the modules they lead to may be unrelated.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::VisLevel;
+///
+/// let level = |x: &str| VisLevel::from_tokens(x.parse().unwrap()).unwrap();
+/// assert!(level("pub(crate)") < level("pub"));
+/// assert!(level("pub(in crate::a)") > level("pub(in crate::a::b)"));
+/// assert_eq!(level("pub(super)").partial_cmp(&level("pub(in crate::a)")), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VisLevel {
+    /// No visibility, or `pub(self)`.
+    Private,
+    /// `pub(super)`.
+    Super,
+    /// `pub(in path)`, other than `pub(in self)`, `pub(in super)` and
+    /// `pub(in crate)`, given by the identifiers of the path.
+    InPath(Vec<Ident>),
+    /// `pub(crate)`.
+    Crate,
+    /// `pub`.
+    Public,
+}
+
+impl VisLevel {
+    /// Parses a visibility modifier, or returns `None` if it isn't one.
+    pub fn from_tokens(visibility: TokenStream) -> Option<Self> {
+        validate_visibility(visibility.clone()).ok()?;
+        Self::of(&visibility.into_iter().collect::<Vec<_>>())
+    }
+
+    /// Finds the level of a visibility removed from an item, which may
+    /// be `pub in path` if the parentheses have been lost.
+    fn of(tokens: &[TokenTree]) -> Option<Self> {
+        let from_path = |path: &[TokenTree]| {
+            let idents: Vec<Ident> = path
+                .iter()
+                .filter_map(|x| match x {
+                    TokenTree::Ident(y) => Some(y.clone()),
+                    _ => None,
+                })
+                .collect();
+            match &idents[..] {
+                [] => None,
+                [x] if *x == "self" => Some(Self::Private),
+                [x] if *x == "super" => Some(Self::Super),
+                [x] if *x == "crate" => Some(Self::Crate),
+                _ => Some(Self::InPath(idents)),
+            }
+        };
+
+        match tokens {
+            [] => Some(Self::Private),
+            [_] => Some(Self::Public),
+            [_, TokenTree::Group(x)] => {
+                let inner: Vec<TokenTree> = x.stream().into_iter().collect();
+                match &inner[..] {
+                    [y, path @ ..] if is_ident(y, "in") => from_path(path),
+                    [_] => from_path(&inner),
+                    _ => None,
+                }
+            }
+            [_, x, path @ ..] if is_ident(x, "in") => from_path(path),
+            _ => None,
+        }
+    }
+}
+
+impl PartialOrd for VisLevel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        use VisLevel::*;
+
+        // `pub(super)` is the same as `pub(in super)`.
+        let path = |x: &Self| match x {
+            Super => Some(alloc::vec![Ident::new("super", Span::call_site())]),
+            InPath(x) => Some(x.clone()),
+            _ => None,
+        };
+
+        match (self, other) {
+            _ if self == other => Some(Ordering::Equal),
+            (Private, _) | (_, Public) => Some(Ordering::Less),
+            (_, Private) | (Public, _) => Some(Ordering::Greater),
+            (_, Crate) => Some(Ordering::Less),
+            (Crate, _) => Some(Ordering::Greater),
+            _ => {
+                let (a, b) = (path(self)?, path(other)?);
+                let (a_crate, a_up, a_down) = split_path(&a);
+                let (b_crate, b_up, b_down) = split_path(&b);
+                // A module contains the modules inside it. Only the
+                // `super`s of a path are known to be such ancestors, as
+                // the names of the modules they skip aren't known.
+                match (a_crate == b_crate, a_up.cmp(&b_up)) {
+                    (false, _) => None,
+                    (true, Ordering::Equal) if a_down == b_down => Some(Ordering::Equal),
+                    (true, Ordering::Equal) if b_down.starts_with(a_down) => {
+                        Some(Ordering::Greater)
+                    }
+                    (true, Ordering::Equal) if a_down.starts_with(b_down) => Some(Ordering::Less),
+                    (true, Ordering::Greater) if a_down.is_empty() => Some(Ordering::Greater),
+                    (true, Ordering::Less) if b_down.is_empty() => Some(Ordering::Less),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Splits the path of a `pub(in path)` visibility into whether it starts
+/// at `crate`, how many `super`s it starts with, and the rest.
+fn split_path(path: &[Ident]) -> (bool, usize, &[Ident]) {
+    match path {
+        [x, rest @ ..] if *x == "crate" => (true, 0, rest),
+        [x, rest @ ..] if *x == "self" => split_path(rest),
+        _ => {
+            let up = path.iter().take_while(|x| **x == "super").count();
+            (false, up, &path[up..])
+        }
+    }
+}
+
+/// Compares how widely visible two visibility modifiers are, as
+/// [`VisLevel`]s.
+fn compare_visibility(a: &[TokenTree], b: &[TokenTree]) -> Option<Ordering> {
+    VisLevel::of(a)?.partial_cmp(&VisLevel::of(b)?)
+}
+
+/// Checks for the `::core::compile_error!{..}` of [`compile_error`].
+fn is_compile_error(item: &[TokenTree]) -> bool {
+    matches!(item, [x, _, _, _, _, y, ..] if is_punct(x, ':') && is_ident(y, "compile_error"))
+}
+
+/// Builds a `compile_error!` invocation with the given message.
+///
+/// Every generated token is given `span` so that the error is
+/// reported at the offending input.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let punct = |ch, spacing| {
+        let mut x = Punct::new(ch, spacing);
+        x.set_span(span);
+        TokenTree::from(x)
+    };
+
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut body = Group::new(Delimiter::Brace, TokenTree::from(message).into());
+    body.set_span(span);
+
+    [
+        punct(':', Spacing::Joint),
+        punct(':', Spacing::Alone),
+        Ident::new("core", span).into(),
+        punct(':', Spacing::Joint),
+        punct(':', Spacing::Alone),
+        Ident::new("compile_error", span).into(),
+        punct('!', Spacing::Alone),
+        body.into(),
+    ]
+    .into_iter()
+    .collect()
+}
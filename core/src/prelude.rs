@@ -0,0 +1,10 @@
+//! The items most procedural macros built on this crate need.
+//!
+//! ```
+//! use visibility_attribute_core::prelude::*;
+//! ```
+
+pub use crate::{
+    peek_visibility, prepend_visibility, set_visibility, strip_visibility, try_set_visibility,
+    VisLevel, VisibilityError, VisibilityRewriter,
+};
@@ -0,0 +1,59 @@
+//! Helpers for the test suites of crates built on top of this one.
+//!
+//! They run on [`proc_macro2`] tokens, so transformations can be checked
+//! without a compiler running a procedural macro.
+
+use alloc::string::ToString;
+use proc_macro2::TokenStream;
+
+/// Replaces the visibility modifier of an item.
+///
+/// This is the same as [`set_visibility`](crate::set_visibility).
+pub fn inner_set_visibility2(input: TokenStream, annotated_item: TokenStream) -> TokenStream {
+    crate::set_visibility(input, annotated_item)
+}
+
+/// Removes the visibility modifier from an item.
+///
+/// It returns the removed visibility, which is empty if the item was
+/// private, and the rest of the item including its outer attributes.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::testing::remove_visibility2;
+///
+/// let (visibility, rest) = remove_visibility2("pub(crate) fn f() {}".parse().unwrap());
+/// assert_eq!(visibility.to_string(), "pub (crate)");
+/// assert_eq!(rest.to_string(), "fn f () { }");
+/// ```
+pub fn remove_visibility2(input: TokenStream) -> (TokenStream, TokenStream) {
+    let stripped = crate::remove_visibility(input);
+    let visibility = stripped.visibility.iter().cloned().collect();
+    (visibility, stripped.rest().collect())
+}
+
+/// Asserts that an item has the given visibility modifier.
+///
+/// `expected` is compared as tokens, so spacing doesn't matter. It is
+/// empty for a private item.
+///
+/// # Panics
+/// Panics if the visibility differs, or if `expected` isn't valid tokens.
+///
+/// # Examples
+/// ```
+/// use visibility_attribute_core::testing::assert_visibility;
+///
+/// assert_visibility("pub(crate) fn f() {}".parse().unwrap(), "pub(crate)");
+/// assert_visibility("fn f() {}".parse().unwrap(), "");
+/// ```
+#[track_caller]
+pub fn assert_visibility(item: TokenStream, expected: &str) {
+    let visibility = crate::peek_visibility(&item).unwrap_or_default();
+    let expected: TokenStream = expected.parse().expect("`expected` should be valid tokens");
+    assert_eq!(
+        visibility.to_string(),
+        expected.to_string(),
+        "unexpected visibility of `{item}`"
+    );
+}
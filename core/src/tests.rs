@@ -0,0 +1,2499 @@
+use super::{
+    clamp_visibility, compare_visibility, describe_change, expose_to_crate, expose_to_super,
+    is_ident, is_paren_group, is_punct, make_public, peek_visibility, prepend_visibility,
+    remove_visibility, resolve_from_env, set_visibility, set_visibility_block, split_items,
+    strip_all_visibility, strip_visibility, test_visible, try_set_visibility,
+    visibility_for_feature, VisLevel, VisibilityError, VisibilityRewriter,
+};
+use itertools::iproduct;
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::quote;
+
+struct SampleStreams {
+    visibilities: Box<[TokenStream]>,
+    malformed: Box<[TokenStream]>,
+    bases: Box<[TokenStream]>,
+    non_items: Box<[TokenStream]>,
+}
+
+impl SampleStreams {
+    /// Both the valid and malformed visibilities.
+    fn prefixes(&self) -> impl Iterator<Item = &TokenStream> + Clone {
+        self.visibilities.iter().chain(self.malformed.iter())
+    }
+}
+
+fn get_sample_streams() -> SampleStreams {
+    SampleStreams {
+        visibilities: Box::new([
+            TokenStream::new(),
+            quote! { pub },
+            quote! { pub(crate) },
+            quote! { pub(self) },
+            quote! { pub(super) },
+            quote! { pub(in crate::a) },
+            quote! { pub(in crate::a::b) },
+            quote! { pub(in super::super) },
+            quote! { pub(in self) },
+            quote! { pub(in super) },
+            quote! { pub(in crate) },
+            // Spacing isn't kept in tokens, apart from that of puncts.
+            "pub ( crate )".parse().unwrap(),
+            "pub(in  crate :: a)".parse().unwrap(),
+            "pub(/* comment */ super)".parse().unwrap(),
+        ]),
+        malformed: Box::new([
+            quote! { pub(super::super) },
+            quote! { pub(super::super::super) },
+            quote! { pub() },
+            quote! { pub(! this is ; nonsense) },
+            quote! { pub in crate::a::b },
+            quote! { pub in super::super },
+        ]),
+        bases: Box::new([
+            quote! { 5 },
+            quote! { fn add(a: i32, b: i32) -> i32 { a + b } },
+            quote! { async fn f() {} },
+            quote! { const unsafe fn f() {} },
+            quote! { async unsafe fn f() {} },
+            quote! { extern "C" fn f() {} },
+            quote! { use foo::Bar; },
+            quote! { use foo::Bar as Baz; },
+            quote! { use foo::Trait as _; },
+            quote! { extern crate foo; },
+            quote! { extern crate foo as bar; },
+            quote! { use foo::{bar::Bar, Baz as Qux}; },
+            quote! { const X: i32 = 1; },
+            quote! { static Y: u8 = 0; },
+            quote! { static mut W: [u8; 2] = [0, 1]; },
+            quote! { type Z = u8; },
+            quote! { mod foo; },
+            // Only on nightly, but they lex the same on stable.
+            quote! { auto trait Foo {} },
+            quote! { unsafe auto trait Foo {} },
+            quote! { trait Bar = Clone + Send; },
+            quote! { trait Bar<T> = Iterator<Item = T> where T: Copy; },
+            quote! { union U { pub a: u32, b: f32 } },
+            quote! { mod foo { fn f() {} } },
+            quote! { type Z<T> where T: Copy = Vec<T>; },
+            quote! { fn f<T: Trait>(x: T) -> Option<T> where T: Clone + Fn(u8) -> u8 { Some(x) } },
+            quote! { struct Ref<'a, T: ?Sized + 'a> { r: &'a T } },
+            quote! { struct Pair<'a, const N: usize>(&'a [u8; N], Box<dyn Fn() -> u8 + 'a>); },
+            quote! { fn café(naïve: Größe) -> 東京 {} },
+            quote! { fn public(pubs: u8) {} },
+            quote! { public fn f() {} },
+            quote! { [] },
+            quote! { {super} },
+        ]),
+        non_items: Box::new([
+            quote! { let mut four = 2.add(2) },
+            quote! { impl Foo {} },
+            quote! { #[inline] impl Foo {} },
+            quote! { unsafe impl Send for Foo {} },
+            quote! { for i in 0..5 {} },
+        ]),
+    }
+}
+
+#[test]
+fn token_helper_test() {
+    let tokens: Vec<TokenTree> = quote! { pub r#pub (crate) [x] :: # }.into_iter().collect();
+    assert!(is_ident(&tokens[0], "pub"));
+    assert!(!is_ident(&tokens[0], "pu"));
+    assert!(!is_ident(&tokens[1], "pub"));
+    assert!(!is_ident(&tokens[2], "crate"));
+
+    assert!(is_paren_group(&tokens[2]));
+    assert!(!is_paren_group(&tokens[3]));
+    assert!(!is_paren_group(&tokens[0]));
+
+    // Both the joint and the alone `:` of `::` match.
+    assert!(is_punct(&tokens[4], ':'));
+    assert!(is_punct(&tokens[5], ':'));
+    assert!(is_punct(&tokens[6], '#'));
+    assert!(!is_punct(&tokens[6], ':'));
+    assert!(!is_punct(&tokens[0], ':'));
+}
+
+#[test]
+fn remove_visibility_test() {
+    let samples = get_sample_streams();
+    let empty = [TokenStream::new()];
+    let items = samples
+        .bases
+        .iter()
+        .chain(samples.non_items.iter())
+        .chain(&empty);
+    let comb = iproduct!(items, samples.prefixes());
+
+    for (b, p) in comb {
+        let stripped = remove_visibility(quote! { #p #b });
+        assert_eq!(
+            stripped
+                .visibility
+                .iter()
+                .cloned()
+                .collect::<TokenStream>()
+                .to_string(),
+            p.to_string()
+        );
+        assert_eq!(
+            stripped.rest().collect::<TokenStream>().to_string(),
+            b.to_string()
+        );
+    }
+}
+
+#[test]
+fn inner_set_visibility_test() {
+    let samples = get_sample_streams();
+    let comb = iproduct!(
+        samples.visibilities.iter(),
+        samples.bases.iter(),
+        samples.prefixes()
+    );
+
+    for (v, b, p) in comb {
+        // `pub(self)` is normalized to private.
+        let expected = if *v.to_string() == *"pub (self)" {
+            TokenStream::new()
+        } else {
+            v.to_owned()
+        };
+        assert_eq!(
+            set_visibility(v.to_owned(), quote! { #p #b }).to_string(),
+            quote! { #expected #b }.to_string()
+        );
+    }
+}
+
+#[test]
+fn idempotency_test() {
+    let samples = get_sample_streams();
+    let comb = iproduct!(
+        samples.visibilities.iter(),
+        samples.bases.iter(),
+        samples.prefixes()
+    );
+
+    for (v, b, p) in comb {
+        let item = quote! { #[inline] #p #b };
+        let once = set_visibility(v.to_owned(), item);
+        let twice = set_visibility(v.to_owned(), once.clone());
+        assert_eq!(twice.to_string(), once.to_string());
+    }
+}
+
+/// Flattens a stream into its tokens, recording the spacing of each
+/// punct and the delimiter of each group, which `to_string` can hide.
+fn token_shapes(input: TokenStream) -> Vec<String> {
+    let mut shapes = Vec::new();
+    for tt in input {
+        match tt {
+            TokenTree::Group(x) => {
+                shapes.push(format!("{:?} open", x.delimiter()));
+                shapes.extend(token_shapes(x.stream()));
+                shapes.push(format!("{:?} close", x.delimiter()));
+            }
+            TokenTree::Punct(x) => shapes.push(format!("{} {:?}", x.as_char(), x.spacing())),
+            x => shapes.push(x.to_string()),
+        }
+    }
+    shapes
+}
+
+#[test]
+fn nested_group_round_trip_test() {
+    let samples = get_sample_streams();
+    let nested = [
+        quote! { fn add(a: i32, b: i32) -> i32 { a + b } },
+        quote! { fn f() -> Vec<Vec<u8>> { vec![vec![1], vec![]] } },
+        quote! { fn g<'a>(x: &'a [(u8, u8)]) -> &'a u8 { &x[0].0 } },
+        quote! { fn h() { let _ = |x: u8| -> u8 { x >> 1 }; } },
+        quote! { struct S { f: [(u8, (i8, i8)); 2] } },
+    ];
+    let items = samples.bases.iter().chain(nested.iter());
+    let comb = iproduct!(items, samples.visibilities.iter());
+
+    for (b, p) in comb {
+        let out = set_visibility(quote! { pub }, quote! { #p #b });
+        let expected = quote! { pub #b };
+        assert_eq!(out.to_string(), expected.to_string());
+        assert_eq!(token_shapes(out), token_shapes(expected));
+    }
+}
+
+#[test]
+fn self_visibility_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(self) }, quote! { pub fn f() {} }).to_string(),
+        set_visibility(TokenStream::new(), quote! { pub fn f() {} }).to_string()
+    );
+}
+
+#[test]
+fn legacy_crate_visibility_test() {
+    assert_eq!(
+        set_visibility(quote! { crate }, quote! { pub fn f() {} }).to_string(),
+        quote! { pub(crate) fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn invalid_visibility_test() {
+    let samples = get_sample_streams();
+    let inputs = samples
+        .malformed
+        .iter()
+        .cloned()
+        .chain([quote! { not_a_vis }]);
+    let comb = iproduct!(inputs, samples.bases.iter());
+
+    for (v, b) in comb {
+        assert!(set_visibility(v, b.to_owned())
+            .to_string()
+            .contains("compile_error"));
+    }
+}
+
+#[test]
+fn string_visibility_test() {
+    assert_eq!(
+        set_visibility(quote! { "pub" }, quote! { fn f() {} }).to_string(),
+        quote! { pub fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { "pub(crate)", fields }, quote! { struct S(u8); }).to_string(),
+        quote! { struct S(pub(crate) u8); }.to_string()
+    );
+    assert!(set_visibility(quote! { "garbage(" }, quote! { fn f() {} })
+        .to_string()
+        .contains("expected a string containing a visibility"));
+    assert!(set_visibility(quote! { "garbage" }, quote! { fn f() {} })
+        .to_string()
+        .contains("expected a visibility"));
+}
+
+#[test]
+fn path_without_in_test() {
+    for input in [
+        quote! { pub(super::super) },
+        quote! { pub(crate::a::b) },
+        quote! { "pub(self::a)" },
+    ] {
+        let out = set_visibility(input, quote! { fn f() {} }).to_string();
+        assert!(out.contains("a path in a visibility needs `in`"), "{out}");
+    }
+    let out = set_visibility(quote! { pub(nowhere) }, quote! { fn f() {} }).to_string();
+    assert!(!out.contains("needs `in`"), "{out}");
+    let out = clamp_visibility(quote! { pub(super::super) }, quote! { fn f() {} }).to_string();
+    assert!(out.contains("needs `in`"), "{out}");
+}
+
+#[test]
+fn trailing_token_test() {
+    for input in ["pub(crate) junk", "pub junk", "pub(in crate::a) fields"] {
+        let error = try_set_visibility(input.parse().unwrap(), quote! { fn f() {} }).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unexpected token after the visibility; separate options with commas"
+        );
+        // The error points at the first unexpected token.
+        let column = input.rfind(' ').unwrap() + 1;
+        assert_eq!(error.span().start().column, column, "{input}");
+    }
+    let out = set_visibility(quote! { pub(crate) junk }, quote! { fn f() {} }).to_string();
+    assert!(out.ends_with(&quote! { fn f() {} }.to_string()), "{out}");
+}
+
+#[test]
+fn empty_restriction_test() {
+    for f in [set_visibility, clamp_visibility] {
+        let out = f(quote! { pub() }, quote! { fn f() {} }).to_string();
+        assert!(out.contains("`pub()` isn't a visibility; use `pub(crate)` or `pub` instead"));
+    }
+}
+
+#[test]
+fn use_visibility_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, quote! { pub use foo::Bar as Baz; }).to_string(),
+        quote! { pub(crate) use foo::Bar as Baz; }.to_string()
+    );
+}
+
+#[test]
+fn qualifiers_survive_test() {
+    for qualifiers in [
+        quote! { async },
+        quote! { unsafe },
+        quote! { const },
+        quote! { extern "C" },
+        quote! { async unsafe },
+        quote! { const unsafe extern "C" },
+    ] {
+        assert_eq!(
+            strip_visibility(quote! { pub #qualifiers fn f() {} }).to_string(),
+            quote! { #qualifiers fn f() {} }.to_string()
+        );
+        assert_eq!(
+            set_visibility(quote! { pub(crate) }, quote! { #qualifiers fn f() {} }).to_string(),
+            quote! { pub(crate) #qualifiers fn f() {} }.to_string()
+        );
+    }
+}
+
+#[test]
+fn remove_in_path_visibility_test() {
+    for p in [
+        quote! { pub(in crate::a::b) },
+        quote! { pub(in super::super) },
+        quote! { pub in super::super },
+    ] {
+        assert!(remove_visibility(p).rest().next().is_none());
+    }
+}
+
+#[test]
+fn raw_pub_identifier_test() {
+    for b in [
+        quote! { r#pub },
+        quote! { r#pub(u8) },
+        quote! { r#pub::Foo },
+        quote! { struct r#pub; },
+    ] {
+        let stripped = remove_visibility(b.clone());
+        assert!(stripped.visibility.is_empty());
+        assert_eq!(
+            stripped.rest().collect::<TokenStream>().to_string(),
+            b.to_string()
+        );
+    }
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! { struct S(r#pub, u8); }
+        )
+        .to_string(),
+        quote! { struct S(pub(crate) r#pub, pub(crate) u8); }.to_string()
+    );
+}
+
+#[test]
+fn uncommon_restriction_test() {
+    for (v, field) in [
+        (quote! { pub(in self) }, quote! { pub(in super) u8 }),
+        (
+            quote! { pub(in super::super) },
+            quote! { pub(in crate::a::b) u8 },
+        ),
+        (quote! { pub(in crate::a::b) }, quote! { pub(in self) u8 }),
+    ] {
+        assert_eq!(
+            set_visibility(quote! { #v, fields }, quote! { struct S(#field); }).to_string(),
+            quote! { struct S(#v u8); }.to_string()
+        );
+    }
+}
+
+#[test]
+fn remove_visibility_after_attributes_test() {
+    assert_eq!(
+        remove_visibility(quote! { #[inline] pub fn f(){} })
+            .rest()
+            .collect::<TokenStream>()
+            .to_string(),
+        quote! { #[inline] fn f(){} }.to_string()
+    );
+    assert_eq!(
+        remove_visibility(quote! { #[inline] #[must_use] pub(crate) fn f(){} })
+            .rest()
+            .collect::<TokenStream>()
+            .to_string(),
+        quote! { #[inline] #[must_use] fn f(){} }.to_string()
+    );
+}
+
+#[test]
+fn comment_in_visibility_test() {
+    // A doc comment is lexed as an attribute, so it is a token.
+    let item: TokenStream = "pub(/** comment */ crate) fn f() {}".parse().unwrap();
+    assert_eq!(
+        strip_visibility(item.clone()).to_string(),
+        quote! { fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub }, item).to_string(),
+        quote! { pub fn f() {} }.to_string()
+    );
+
+    let input: TokenStream = "pub(/** comment */ crate)".parse().unwrap();
+    let out = set_visibility(input, quote! { fn f() {} }).to_string();
+    assert!(out.contains("compile_error"), "{out}");
+}
+
+#[test]
+fn set_visibility_after_attributes_test() {
+    assert_eq!(
+        set_visibility(quote! { pub }, quote! { #[inline] fn f(){} }).to_string(),
+        quote! { #[inline] pub fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate) },
+            quote! { #[inline] #[must_use] pub fn f(){} }
+        )
+        .to_string(),
+        quote! { #[inline] #[must_use] pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub, fields },
+            quote! { struct S { #[doc = "hi"] a: u8 } }
+        )
+        .to_string(),
+        quote! { struct S { #[doc = "hi"] pub a: u8 } }.to_string()
+    );
+}
+
+#[test]
+fn path_attribute_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate) },
+            quote! { #[foo::bar(baz)] pub fn f(){} }
+        )
+        .to_string(),
+        quote! { #[foo::bar(baz)] pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        strip_visibility(quote! { #[::foo::bar = "baz"] #[inline] pub fn f(){} }).to_string(),
+        quote! { #[::foo::bar = "baz"] #[inline] fn f(){} }.to_string()
+    );
+
+    // Inner attributes aren't skipped like outer ones.
+    let item = quote! { #![allow(dead_code)] pub fn f(){} };
+    assert_eq!(strip_visibility(item.clone()).to_string(), item.to_string());
+    let out = set_visibility(quote! { pub(crate) }, item.clone()).to_string();
+    assert!(
+        out.contains("inner attributes like `#![..]` can't be on an item"),
+        "{out}"
+    );
+    assert!(out.ends_with(&item.to_string()), "{out}");
+    let out = set_visibility(quote! { pub(crate) }, "# pub fn f() {}".parse().unwrap()).to_string();
+    assert!(out.contains("expected `[` after `#`"), "{out}");
+}
+
+#[test]
+fn cfg_attribute_order_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate) },
+            quote! { #[cfg(feature = "x")] pub fn f(){} }
+        )
+        .to_string(),
+        quote! { #[cfg(feature = "x")] pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub },
+            quote! { #[inline] #[cfg(feature = "x")] #[cfg(test)] fn f(){} }
+        )
+        .to_string(),
+        quote! { #[inline] #[cfg(feature = "x")] #[cfg(test)] pub fn f(){} }.to_string()
+    );
+}
+
+#[test]
+fn remove_visibility_after_doc_comments_test() {
+    assert_eq!(
+        strip_visibility(quote! { #[doc = "hi"] pub fn f(){} }).to_string(),
+        quote! { #[doc = "hi"] fn f(){} }.to_string()
+    );
+
+    // Doc comments are turned into `#[doc = "..."]` attributes when lexed.
+    let item: TokenStream = "/// hi\n/// there\npub(crate) fn f(){}".parse().unwrap();
+    assert_eq!(
+        strip_visibility(item).to_string(),
+        quote! { #[doc = " hi"] #[doc = " there"] fn f(){} }.to_string()
+    );
+}
+
+#[test]
+fn visibility_span_test() {
+    // Parsed streams carry real locations, unlike `quote!`'s call site spans.
+    let item: TokenStream = "\n    pub fn f() {}".parse().unwrap();
+    let pub_start = item.clone().into_iter().next().unwrap().span().start();
+    assert_ne!(pub_start, Span::call_site().start());
+
+    let output = set_visibility(quote! { pub(crate) }, item);
+    let mut tt_iter = output.into_iter();
+    assert_eq!(tt_iter.next().unwrap().span().start(), pub_start);
+    match tt_iter.next().unwrap() {
+        TokenTree::Group(x) => {
+            for tt in x.stream() {
+                assert_eq!(tt.span().start(), pub_start);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn clamp_visibility_test() {
+    // From narrowest to widest.
+    let levels = [
+        TokenStream::new(),
+        quote! { pub(super) },
+        quote! { pub(crate) },
+        quote! { pub },
+    ];
+    let comb = iproduct!(levels.iter().enumerate(), levels.iter().enumerate());
+
+    for ((i, existing), (j, ceiling)) in comb {
+        let expected = if i > j { ceiling } else { existing };
+        assert_eq!(
+            clamp_visibility(ceiling.to_owned(), quote! { #existing fn f() {} }).to_string(),
+            quote! { #expected fn f() {} }.to_string()
+        );
+    }
+}
+
+#[test]
+fn vis_level_test() {
+    use std::cmp::Ordering::*;
+
+    let level = |x: TokenStream| VisLevel::from_tokens(x).unwrap();
+    assert_eq!(level(quote! {}), VisLevel::Private);
+    assert_eq!(level(quote! { pub(self) }), VisLevel::Private);
+    assert_eq!(level(quote! { pub(in super) }), VisLevel::Super);
+    assert_eq!(level(quote! { pub(crate) }), VisLevel::Crate);
+    assert_eq!(level(quote! { pub }), VisLevel::Public);
+    assert!(matches!(level(quote! { pub(in crate::a) }), VisLevel::InPath(x) if x.len() == 2));
+    for invalid in [
+        quote! { pub() },
+        quote! { pub in crate::a },
+        quote! { crate::a },
+    ] {
+        assert_eq!(VisLevel::from_tokens(invalid), None);
+    }
+
+    for (a, b, expected) in [
+        (quote! {}, quote! { pub(super) }, Some(Less)),
+        (quote! { pub(super) }, quote! { pub(crate) }, Some(Less)),
+        (quote! { pub(crate) }, quote! { pub }, Some(Less)),
+        (quote! { pub(in self) }, quote! { pub(self) }, Some(Equal)),
+        (
+            quote! { pub(in crate::a) },
+            quote! { pub(in crate::a) },
+            Some(Equal),
+        ),
+        (
+            quote! { pub(in crate::a) },
+            quote! { pub(in crate::a::b) },
+            Some(Greater),
+        ),
+        (
+            quote! { pub(in crate::a) },
+            quote! { pub(crate) },
+            Some(Less),
+        ),
+        (
+            quote! { pub(in super::super) },
+            quote! { pub(super) },
+            Some(Greater),
+        ),
+        (
+            quote! { pub(in super::super) },
+            quote! { pub(crate) },
+            Some(Less),
+        ),
+        (
+            quote! { pub(in crate::a) },
+            quote! { pub(in crate::b) },
+            None,
+        ),
+        (quote! { pub(in crate::a) }, quote! { pub(super) }, None),
+        (
+            quote! { pub(in crate::a) },
+            quote! { pub(in super::super) },
+            None,
+        ),
+        (
+            quote! { pub(in super) },
+            quote! { pub(in super::a) },
+            Some(Greater),
+        ),
+        (
+            quote! { pub(in super::super) },
+            quote! { pub(in super::a) },
+            Some(Greater),
+        ),
+        (
+            quote! { pub(in super::a) },
+            quote! { pub(in super::super::b) },
+            None,
+        ),
+        (
+            quote! { pub(in super::a) },
+            quote! { pub(in super::b) },
+            None,
+        ),
+    ] {
+        let (a, b) = (level(a), level(b));
+        assert_eq!(a.partial_cmp(&b), expected, "{a:?} {b:?}");
+        assert_eq!(
+            b.partial_cmp(&a),
+            expected.map(|x| x.reverse()),
+            "{a:?} {b:?}"
+        );
+    }
+}
+
+#[test]
+fn vis_level_transitivity_test() {
+    let levels = [
+        quote! {},
+        quote! { pub(super) },
+        quote! { pub(in super::super) },
+        quote! { pub(in super::a) },
+        quote! { pub(in super::a::b) },
+        quote! { pub(in super::super::b) },
+        quote! { pub(in crate::a) },
+        quote! { pub(in crate::a::b) },
+        quote! { pub(crate) },
+        quote! { pub },
+    ]
+    .map(|x| VisLevel::from_tokens(x).unwrap());
+
+    for (a, b, c) in iproduct!(&levels, &levels, &levels) {
+        assert_eq!(a.partial_cmp(b), b.partial_cmp(a).map(|x| x.reverse()));
+        if a > b && b > c {
+            assert!(a > c, "{a:?} {b:?} {c:?}");
+        }
+        if a == b && b == c {
+            assert!(a == c, "{a:?} {b:?} {c:?}");
+        }
+    }
+}
+
+#[test]
+fn compare_in_path_visibility_test() {
+    use std::cmp::Ordering::*;
+
+    let in_path = quote! { pub(in crate::a) };
+    for (other, expected) in [
+        (TokenStream::new(), Some(Greater)),
+        (quote! { pub(self) }, Some(Greater)),
+        (quote! { pub(super) }, None),
+        (quote! { pub(in crate::a) }, Some(Equal)),
+        (quote! { pub(in crate::b) }, None),
+        (quote! { pub(crate) }, Some(Less)),
+        (quote! { pub }, Some(Less)),
+    ] {
+        let a: Vec<_> = in_path.clone().into_iter().collect();
+        let b: Vec<_> = other.into_iter().collect();
+        assert_eq!(compare_visibility(&a, &b), expected);
+        assert_eq!(compare_visibility(&b, &a), expected.map(|x| x.reverse()));
+    }
+}
+
+#[test]
+fn set_field_visibility_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! {
+                pub struct Named<T: Fn(u8) -> u8> where T: Clone {
+                    a: HashMap<u8, T>,
+                    pub b: u8,
+                    pub(super) c: fn(u8, u8) -> u8,
+                }
+            }
+        )
+        .to_string(),
+        quote! {
+            pub struct Named<T: Fn(u8) -> u8> where T: Clone {
+                pub(crate) a: HashMap<u8, T>,
+                pub(crate) b: u8,
+                pub(crate) c: fn(u8, u8) -> u8,
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! { struct Tuple<F: Fn(u8)>(pub F, u8) where F: Copy; }
+        )
+        .to_string(),
+        quote! { struct Tuple<F: Fn(u8)>(pub(crate) F, pub(crate) u8) where F: Copy; }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { , fields }, quote! { pub struct Unit; }).to_string(),
+        quote! { pub struct Unit; }.to_string()
+    );
+    assert!(
+        set_visibility(quote! { pub, fields }, quote! { pub fn f() {} })
+            .to_string()
+            .contains("compile_error")
+    );
+}
+
+#[test]
+fn set_item_and_field_visibility_test() {
+    let item = quote! { pub struct S { pub a: u8, b: u8 } };
+    for (input, expected) in [
+        (
+            quote! { pub(crate), fields = pub(super) },
+            quote! { pub(crate) struct S { pub(super) a: u8, pub(super) b: u8 } },
+        ),
+        (
+            quote! { fields = pub(crate), pub },
+            quote! { pub struct S { pub(crate) a: u8, pub(crate) b: u8 } },
+        ),
+        (
+            quote! { pub(crate), fields = },
+            quote! { pub(crate) struct S { a: u8, b: u8 } },
+        ),
+        (
+            quote! { , fields = pub },
+            quote! { struct S { pub a: u8, pub b: u8 } },
+        ),
+        (
+            quote! { inherit, fields = "pub(crate)" },
+            quote! { pub struct S { pub(crate) a: u8, pub(crate) b: u8 } },
+        ),
+    ] {
+        assert_eq!(
+            set_visibility(input, item.clone()).to_string(),
+            expected.to_string()
+        );
+    }
+    assert!(set_visibility(quote! { pub, fields = pub() }, item)
+        .to_string()
+        .contains("`pub()` isn't a visibility"));
+}
+
+#[test]
+fn set_tuple_field_visibility_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(super), fields },
+            quote! { struct Foo(pub i32, pub(crate) Vec<(u8, u8)>); }
+        )
+        .to_string(),
+        quote! { struct Foo(pub(super) i32, pub(super) Vec<(u8, u8)>); }.to_string()
+    );
+    // The parentheses are the field's type rather than a restriction.
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! { struct Pair(pub (u8, u8), (i8, i8),); }
+        )
+        .to_string(),
+        quote! { struct Pair(pub(crate) (u8, u8), pub(crate) (i8, i8),); }.to_string()
+    );
+}
+
+#[test]
+fn set_named_field_visibility_test() {
+    let item = quote! {
+        pub struct Wrapper<T> {
+            pub(super) outer: u8,
+            inner: T,
+        }
+    };
+    assert_eq!(
+        set_visibility(quote! { pub(crate), field = "inner" }, item.clone()).to_string(),
+        quote! {
+            pub struct Wrapper<T> {
+                pub(super) outer: u8,
+                pub(crate) inner: T,
+            }
+        }
+        .to_string()
+    );
+    assert!(
+        set_visibility(quote! { pub(crate), field = "missing" }, item)
+            .to_string()
+            .contains("`field` doesn't name a field of this struct")
+    );
+    assert!(
+        set_visibility(quote! { pub, field = "0" }, quote! { struct S(u8); })
+            .to_string()
+            .contains("compile_error")
+    );
+}
+
+#[test]
+fn unsafe_extern_block_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! {
+                unsafe extern "C" {
+                    fn f();
+                    pub safe fn g(x: i32) -> i32;
+                    unsafe static X: u8;
+                }
+            }
+        )
+        .to_string(),
+        quote! {
+            unsafe extern "C" {
+                pub(crate) fn f();
+                pub(crate) safe fn g(x: i32) -> i32;
+                pub(crate) unsafe static X: u8;
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub, fields },
+            quote! { #[inline] unsafe extern { fn f(); } }
+        )
+        .to_string(),
+        quote! { #[inline] unsafe extern { pub fn f(); } }.to_string()
+    );
+
+    // The block itself can't take a visibility, so `recursive` skips it.
+    assert_eq!(
+        set_visibility(
+            quote! { pub, recursive },
+            quote! { mod m { unsafe extern "C" { fn f(); } } }
+        )
+        .to_string(),
+        quote! { pub mod m { unsafe extern "C" { fn f(); } } }.to_string()
+    );
+
+    // Without `fields`, the block would be given the visibility.
+    for item in [
+        quote! { extern "C" { fn f(); } },
+        quote! { unsafe extern "C" {} },
+        quote! { #[link(name = "m")] extern { static X: u8; } },
+    ] {
+        let error = try_set_visibility(quote! { pub }, item.clone()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`extern` blocks don't take a visibility; \
+            use the `fields` option to set the visibility of their declarations instead",
+            "{item}"
+        );
+    }
+
+    // An `unsafe extern fn` is a function, not a block.
+    assert!(set_visibility(
+        quote! { pub, fields },
+        quote! { unsafe extern "C" fn f() {} }
+    )
+    .to_string()
+    .contains("compile_error"));
+}
+
+#[test]
+fn attributed_field_test() {
+    let item = quote! {
+        struct S {
+            #[serde(skip)] pub x: i32,
+            #[serde(rename = "b")] #[doc = "hi"] y: Vec<u8>,
+        }
+    };
+    assert_eq!(
+        set_visibility(quote! { pub(crate), fields }, item.clone()).to_string(),
+        quote! {
+            struct S {
+                #[serde(skip)] pub(crate) x: i32,
+                #[serde(rename = "b")] #[doc = "hi"] pub(crate) y: Vec<u8>,
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub, field = "y" }, item).to_string(),
+        quote! {
+            struct S {
+                #[serde(skip)] pub x: i32,
+                #[serde(rename = "b")] #[doc = "hi"] pub y: Vec<u8>,
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { , fields },
+            quote! { struct T(#[serde(skip)] pub u8, #[doc = "hi"] pub (u8, u8)); }
+        )
+        .to_string(),
+        quote! { struct T(#[serde(skip)] u8, #[doc = "hi"] (u8, u8)); }.to_string()
+    );
+}
+
+#[test]
+fn union_test() {
+    let item = quote! { #[repr(C)] union U { pub a: u32, b: f32 } };
+    assert_eq!(
+        set_visibility(quote! { pub(crate), fields }, item.clone()).to_string(),
+        quote! { #[repr(C)] union U { pub(crate) a: u32, pub(crate) b: f32 } }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub, field = "b" }, item.clone()).to_string(),
+        quote! { #[repr(C)] union U { pub a: u32, pub b: f32 } }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate), fields = pub }, item).to_string(),
+        quote! { #[repr(C)] pub(crate) union U { pub a: u32, pub b: f32 } }.to_string()
+    );
+
+    // `union` is only a keyword before the union's name.
+    let out = set_visibility(quote! { pub, fields }, quote! { fn union() {} }).to_string();
+    assert!(
+        out.contains("`fields` can only be used on a struct"),
+        "{out}"
+    );
+}
+
+#[test]
+fn set_extern_item_visibility_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), fields },
+            quote! {
+                extern "C" {
+                    pub fn foo(x: i32) -> i32;
+                    fn bar();
+                }
+            }
+        )
+        .to_string(),
+        quote! {
+            extern "C" {
+                pub(crate) fn foo(x: i32) -> i32;
+                pub(crate) fn bar();
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { , fields },
+            quote! { #[link(name = "m")] extern { pub static X: u8; } }
+        )
+        .to_string(),
+        quote! { #[link(name = "m")] extern { static X: u8; } }.to_string()
+    );
+    assert!(
+        set_visibility(quote! { pub, fields }, quote! { extern "C" fn f() {} })
+            .to_string()
+            .contains("compile_error")
+    );
+}
+
+#[test]
+fn non_item_test() {
+    let samples = get_sample_streams();
+    let comb = iproduct!(samples.visibilities.iter(), samples.non_items.iter());
+
+    for (v, b) in comb {
+        assert!(set_visibility(v.to_owned(), b.to_owned())
+            .to_string()
+            .contains("compile_error"));
+    }
+}
+
+#[test]
+fn let_binding_test() {
+    let out = set_visibility(quote! { pub }, quote! { let x = 5 }).to_string();
+    assert!(out.contains("local bindings can't have a visibility"));
+    assert!(out.ends_with(&quote! { let x = 5 }.to_string()));
+}
+
+#[test]
+fn double_visibility_test() {
+    for item in [
+        quote! { pub pub fn f() {} },
+        quote! { pub(crate) pub fn f() {} },
+    ] {
+        let out = set_visibility(quote! { pub }, item.clone()).to_string();
+        assert!(out.contains("the item has more than one visibility"));
+        assert!(out.ends_with(&item.to_string()));
+    }
+    let stripped = remove_visibility(quote! { pub pub fn f() {} });
+    assert_eq!(stripped.visibility.len(), 1);
+}
+
+#[test]
+fn macro_rules_test() {
+    let out = set_visibility(quote! { pub }, quote! { macro_rules! m { () => {} } }).to_string();
+    assert!(out.contains("use `#[macro_export]` instead"));
+    assert!(out.ends_with(&quote! { macro_rules! m { () => {} } }.to_string()));
+    // Only macro definitions are rejected, not other uses of the name.
+    assert_eq!(
+        set_visibility(quote! { pub }, quote! { fn macro_rules() {} }).to_string(),
+        quote! { pub fn macro_rules() {} }.to_string()
+    );
+}
+
+#[test]
+fn strip_visibility_test() {
+    assert_eq!(
+        strip_visibility(quote! { pub(crate) fn x(){} }).to_string(),
+        quote! { fn x(){} }.to_string()
+    );
+}
+
+#[test]
+fn mod_declaration_test() {
+    assert_eq!(
+        strip_visibility(quote! { pub mod foo; }).to_string(),
+        quote! { mod foo; }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, quote! { mod foo; }).to_string(),
+        quote! { pub(crate) mod foo; }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(super) }, quote! { pub mod foo; }).to_string(),
+        quote! { pub(super) mod foo; }.to_string()
+    );
+    let out: Vec<TokenTree> = strip_visibility(quote! { pub mod foo; })
+        .into_iter()
+        .collect();
+    assert!(matches!(out.last(), Some(TokenTree::Punct(x)) if x.as_char() == ';'));
+}
+
+#[test]
+fn prepend_visibility_test() {
+    let visibility = quote! { pub(crate) };
+    assert_eq!(
+        prepend_visibility(&visibility, quote! { fn get_x(&self) -> i32 { self.x } }).to_string(),
+        quote! { pub(crate) fn get_x(&self) -> i32 { self.x } }.to_string()
+    );
+    assert_eq!(
+        prepend_visibility(&visibility, quote! { #[inline] #[must_use] fn f(){} }).to_string(),
+        quote! { #[inline] #[must_use] pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        prepend_visibility(&TokenStream::new(), quote! { fn f(){} }).to_string(),
+        quote! { fn f(){} }.to_string()
+    );
+}
+
+#[test]
+fn extern_crate_test() {
+    assert_eq!(
+        strip_visibility(quote! { pub extern crate foo as bar; }).to_string(),
+        quote! { extern crate foo as bar; }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, quote! { extern crate foo; }).to_string(),
+        quote! { pub(crate) extern crate foo; }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub },
+            quote! { #[macro_use] extern crate self as bar; }
+        )
+        .to_string(),
+        quote! { #[macro_use] pub extern crate self as bar; }.to_string()
+    );
+
+    // Unlike an `extern` block, it has no declarations to rewrite.
+    let out = set_visibility(quote! { pub, fields }, quote! { extern crate foo; }).to_string();
+    assert!(out.contains("compile_error"), "{out}");
+}
+
+#[test]
+fn anonymous_use_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, quote! { pub use foo::Trait as _; }).to_string(),
+        quote! { pub(crate) use foo::Trait as _; }.to_string()
+    );
+    let out: Vec<TokenTree> =
+        set_visibility(quote! { pub(crate) }, quote! { use foo::Trait as _; })
+            .into_iter()
+            .collect();
+    let tail: Vec<String> = out[out.len() - 3..].iter().map(|x| x.to_string()).collect();
+    assert_eq!(tail, ["as", "_", ";"]);
+    assert_eq!(
+        set_visibility_block(quote! { pub; use foo::A as _; use foo::B as _; }).to_string(),
+        quote! { pub use foo::A as _; pub use foo::B as _; }.to_string()
+    );
+}
+
+#[test]
+fn peek_visibility_test() {
+    assert_eq!(
+        peek_visibility(&quote! { pub(crate) fn x(){} }).map(|x| x.to_string()),
+        Some(quote! { pub(crate) }.to_string())
+    );
+    assert_eq!(
+        peek_visibility(&quote! { #[inline] pub fn x(){} }).map(|x| x.to_string()),
+        Some(quote! { pub }.to_string())
+    );
+    assert!(peek_visibility(&quote! { fn x(){} }).is_none());
+    assert!(peek_visibility(&quote! { #[inline] fn x(){} }).is_none());
+}
+
+#[test]
+fn describe_change_test() {
+    let describe = |input: TokenStream, item: TokenStream| {
+        describe_change(&item, &set_visibility(input, item.clone()))
+    };
+    assert_eq!(
+        describe(quote! { pub(crate), debug }, quote! { fn f() {} }).unwrap(),
+        "`set_visibility` changed private to `pub (crate)`"
+    );
+    assert_eq!(
+        describe(quote! { pub if feature = "x" }, quote! { fn f() {} }).unwrap(),
+        "`set_visibility` changed private to `pub` if `feature = \"x\"`, \
+        and to private if `not (feature = \"x\")`"
+    );
+    assert_eq!(describe(quote! { pub }, quote! { impl Foo {} }), None);
+}
+
+#[test]
+fn set_visibility_block_test() {
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            fn add(a: i32, b: i32) -> i32 { a + b }
+            pub fn sub<T: Into<i32>>(a: T, b: T) -> i32 { a.into() - b.into() }
+            pub(super) struct Point { x: i32, y: i32 }
+        })
+        .to_string(),
+        quote! {
+            pub(crate) fn add(a: i32, b: i32) -> i32 { a + b }
+            pub(crate) fn sub<T: Into<i32>>(a: T, b: T) -> i32 { a.into() - b.into() }
+            pub(crate) struct Point { x: i32, y: i32 }
+        }
+        .to_string()
+    );
+    assert!(set_visibility_block(quote! { pub(crate) })
+        .to_string()
+        .contains("expected `;` after the visibility"));
+    for input in [quote! { pub(crate); }, quote! { ; }] {
+        assert!(set_visibility_block(input)
+            .to_string()
+            .contains("no items to apply visibility to"));
+    }
+    assert!(set_visibility_block(TokenStream::new()).is_empty());
+}
+
+#[test]
+fn impl_const_test() {
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(super);
+            impl Foo {
+                pub const N: usize = 3;
+                const PAIR: (u8, [u8; 2]) = (1, [2, { 3 }]);
+                const F: fn(u8, u8) -> u8 = |a, b| { a + b };
+                fn m(){}
+            }
+        })
+        .to_string(),
+        quote! {
+            impl Foo {
+                pub(super) const N: usize = 3;
+                pub(super) const PAIR: (u8, [u8; 2]) = (1, [2, { 3 }]);
+                pub(super) const F: fn(u8, u8) -> u8 = |a, b| { a + b };
+                pub(super) fn m(){}
+            }
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn set_impl_item_visibility_test() {
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            impl<T: Fn(u8) -> u8> Point<T> {
+                fn new() -> Self { todo!() }
+                pub fn x(&self) -> i32 { self.x }
+            }
+        })
+        .to_string(),
+        quote! {
+            impl<T: Fn(u8) -> u8> Point<T> {
+                pub(crate) fn new() -> Self { todo!() }
+                pub(crate) fn x(&self) -> i32 { self.x }
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub;
+            impl<T> Wrapper<T> where for<'a> &'a T: Copy {
+                const N: usize = 1;
+            }
+        })
+        .to_string(),
+        quote! {
+            impl<T> Wrapper<T> where for<'a> &'a T: Copy {
+                pub const N: usize = 1;
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            impl Point {
+                type Output = i32;
+                const ORIGIN: Point = Point { x: 0, y: 0 };
+                fn x(&self) -> Self::Output { self.x }
+            }
+        })
+        .to_string(),
+        quote! {
+            impl Point {
+                type Output = i32;
+                pub(crate) const ORIGIN: Point = Point { x: 0, y: 0 };
+                pub(crate) fn x(&self) -> Self::Output { self.x }
+            }
+        }
+        .to_string()
+    );
+    assert!(set_visibility_block(quote! {
+        pub;
+        impl Clone for Point {
+            fn clone(&self) -> Self { *self }
+        }
+    })
+    .to_string()
+    .contains("items of trait `impl`s can't have a visibility"));
+}
+
+#[test]
+fn enum_and_impl_block_test() {
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            pub enum Shape { Square(u32), Circle { r: u32 } }
+            impl Shape {
+                pub fn square(side: u32) -> Self { Self::Square(side) }
+                fn is_round(&self) -> bool { matches!(self, Self::Circle { .. }) }
+            }
+        })
+        .to_string(),
+        quote! {
+            pub(crate) enum Shape { Square(u32), Circle { r: u32 } }
+            impl Shape {
+                pub(crate) fn square(side: u32) -> Self { Self::Square(side) }
+                pub(crate) fn is_round(&self) -> bool { matches!(self, Self::Circle { .. }) }
+            }
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn split_items_test() {
+    let items = [
+        quote! { pub use a::{b, c}; },
+        quote! { const X: S = S { a: 1 }; },
+        quote! { struct T(u8); },
+        quote! { impl<const N: usize> Foo<{ N }> {} },
+        quote! { fn f() -> impl Fn() -> u8 { || 5 } },
+        quote! { mod m; },
+        quote! { static Y: u8 = 0; },
+        quote! { type Z = u8; },
+    ];
+    let joined: TokenStream = items.iter().cloned().collect();
+
+    assert_eq!(
+        split_items(joined)
+            .into_iter()
+            .map(|x| x.into_iter().collect::<TokenStream>().to_string())
+            .collect::<Vec<_>>(),
+        items.map(|x| x.to_string())
+    );
+}
+
+#[test]
+fn split_initializer_test() {
+    let items = [
+        quote! { const A: bool = 1 < 2; },
+        quote! { fn f() {} },
+        quote! { static B: u32 = 1 << 3; },
+        quote! { struct S { a: u8 } },
+        quote! { const C: fn() -> u8 = || -> u8 { 5 }; },
+        quote! { type D<T> = <T as Tr<1>>::X; },
+        quote! { static E: bool = 3 > 1 && 1 < 3; },
+        quote! { type F = fn(u8) -> u8; },
+        quote! { mod m {} },
+    ];
+    let joined: TokenStream = items.iter().cloned().collect();
+    assert_eq!(
+        split_items(joined.clone())
+            .into_iter()
+            .map(|x| x.into_iter().collect::<TokenStream>().to_string())
+            .collect::<Vec<_>>(),
+        items.clone().map(|x| x.to_string())
+    );
+
+    let block = set_visibility_block(quote! { pub(crate); #joined }).to_string();
+    let expected: TokenStream = items.iter().map(|x| quote! { pub(crate) #x }).collect();
+    assert_eq!(block, expected.to_string());
+}
+
+#[test]
+fn warn_noop_test() {
+    for (v, b, noop) in [
+        (quote! { pub }, quote! { pub fn f() {} }, true),
+        (
+            quote! { pub(crate) },
+            quote! { pub(in crate) fn f() {} },
+            true,
+        ),
+        (quote! {}, quote! { fn f() {} }, true),
+        (quote! { pub(crate) }, quote! { pub fn f() {} }, false),
+        (quote! { pub }, quote! { fn f() {} }, false),
+    ] {
+        let with_flag = set_visibility(quote! { #v, warn_noop }, b.clone()).to_string();
+        assert_eq!(with_flag.contains("deprecated"), noop);
+        assert!(!set_visibility(v, b).to_string().contains("deprecated"));
+    }
+}
+
+#[test]
+fn only_if_private_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(crate), only_if_private }, quote! { fn f() {} }).to_string(),
+        quote! { pub(crate) fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), only_if_private },
+            quote! { pub fn f() {} }
+        )
+        .to_string(),
+        quote! { pub fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn inherit_test() {
+    let item = quote! { pub(super) fn f() {} };
+    assert_eq!(
+        set_visibility(quote! { inherit }, item.clone()).to_string(),
+        item.to_string()
+    );
+    assert!(set_visibility(quote! { inherit }, quote! { impl Foo {} })
+        .to_string()
+        .contains("compile_error"));
+}
+
+#[test]
+fn make_public_test() {
+    for p in get_sample_streams().visibilities.iter() {
+        assert_eq!(
+            make_public(TokenStream::new(), quote! { #p fn f() {} }).to_string(),
+            quote! { pub fn f() {} }.to_string()
+        );
+    }
+    assert!(make_public(quote! { pub }, quote! { fn f() {} })
+        .to_string()
+        .contains("compile_error"));
+}
+
+#[test]
+fn visibility_rewriter_test() {
+    let rewriter = VisibilityRewriter::new().with_visibility(quote! { pub(crate) });
+    for (item, expected) in [
+        (quote! { fn f() {} }, quote! { pub(crate) fn f() {} }),
+        (quote! { pub struct S; }, quote! { pub(crate) struct S; }),
+        (
+            quote! { pub(super) fn g() {} },
+            quote! { pub(crate) fn g() {} },
+        ),
+    ] {
+        assert_eq!(rewriter.apply(item).to_string(), expected.to_string());
+    }
+
+    let stripper = rewriter.clone().strip_only();
+    assert_eq!(
+        stripper.apply(quote! { pub fn f() {} }).to_string(),
+        quote! { fn f() {} }.to_string()
+    );
+
+    let fields = rewriter.fields().only_if_private();
+    assert_eq!(
+        fields.apply(quote! { struct S(u8); }).to_string(),
+        quote! { struct S(pub(crate) u8); }.to_string()
+    );
+    assert_eq!(
+        fields.apply(quote! { pub struct S(u8); }).to_string(),
+        quote! { pub struct S(u8); }.to_string()
+    );
+}
+
+#[test]
+fn argument_order_test() {
+    let item = quote! { struct S(u8); };
+    let expected = quote! { struct S(pub(in crate::a) u8); }.to_string();
+    for input in [
+        quote! { pub(in crate::a), fields },
+        quote! { pub(in crate::a), fields, },
+        quote! { pub(in crate::a) , , fields , },
+        quote! { fields, pub(in crate::a) },
+        quote! { fields, pub(in crate::a), },
+    ] {
+        assert_eq!(set_visibility(input, item.clone()).to_string(), expected);
+    }
+    assert_eq!(
+        set_visibility(quote! { fields }, quote! { struct S(pub u8); }).to_string(),
+        quote! { struct S(u8); }.to_string()
+    );
+    assert!(set_visibility(quote! { pub, pub(crate) }, item)
+        .to_string()
+        .contains("unknown `set_visibility` option"));
+}
+
+#[test]
+fn allow_dead_test() {
+    let item = quote! { pub fn f() {} };
+    assert_eq!(
+        set_visibility(quote! { pub(self), allow_dead }, item.clone()).to_string(),
+        quote! { #[allow(dead_code)] fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate), allow_dead }, item.clone()).to_string(),
+        quote! { pub(crate) fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(self) }, item).to_string(),
+        quote! { fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn try_set_visibility_test() {
+    assert_eq!(
+        try_set_visibility(quote! { pub(crate) }, quote! { pub fn f() {} })
+            .unwrap()
+            .to_string(),
+        quote! { pub(crate) fn f() {} }.to_string()
+    );
+
+    let input = "pub, bogus".parse().unwrap();
+    let Err(VisibilityError::InvalidVisibility { span, .. }) =
+        try_set_visibility(input, quote! { fn f() {} })
+    else {
+        panic!("expected an invalid visibility");
+    };
+    assert_eq!(span.start().column, 5);
+    assert!(matches!(
+        try_set_visibility(quote! { crate::a }, quote! { fn f() {} }),
+        Err(VisibilityError::InvalidVisibility { .. })
+    ));
+    assert!(matches!(
+        try_set_visibility(quote! { pub }, quote! { impl Foo {} }),
+        Err(VisibilityError::UnsupportedItem { .. })
+    ));
+    assert!(matches!(
+        try_set_visibility(quote! { pub, fields }, quote! { fn f() {} }),
+        Err(VisibilityError::UnsupportedItem { .. })
+    ));
+}
+
+#[test]
+fn visibility_error_test() {
+    let error = try_set_visibility(quote! { pub(nowhere) }, quote! { fn f() {} }).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "expected a visibility such as `pub` or `pub(crate)`"
+    );
+    let span = error.span();
+    let tokens: Vec<TokenTree> = error.into_compile_error().into_iter().collect();
+    assert_eq!(
+        TokenStream::from_iter(tokens.clone()).to_string(),
+        quote! { ::core::compile_error!{"expected a visibility such as `pub` or `pub(crate)`"} }
+            .to_string()
+    );
+    assert!(tokens.iter().all(|x| x.span().start() == span.start()));
+
+    let error = try_set_visibility(quote! { pub }, quote! { loop {} }).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "`set_visibility` only applies to items that accept a visibility modifier"
+    );
+}
+
+#[test]
+fn impl_message_test() {
+    for item in [
+        quote! { impl Foo {} },
+        quote! { #[cfg(unix)] impl<T> Foo<T> {} },
+        quote! { unsafe impl Send for Foo {} },
+        quote! { pub unsafe impl Send for Foo {} },
+        quote! { impl !Send for Foo {} },
+        quote! { unsafe impl !Sync for Foo {} },
+    ] {
+        let error = try_set_visibility(quote! { pub }, item.clone()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`impl` blocks don't take a visibility; \
+            apply `set_visibility` to the associated items instead",
+            "{item}"
+        );
+    }
+    assert_eq!(
+        set_visibility(quote! { pub }, quote! { unsafe fn f() {} }).to_string(),
+        quote! { pub unsafe fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn cfg_predicate_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate) if feature = "expose" },
+            quote! { fn f() {} }
+        )
+        .to_string(),
+        quote! {
+            #[cfg(feature = "expose")] pub(crate) fn f() {}
+            #[cfg(not(feature = "expose"))] fn f() {}
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { fields, pub if any(test, doc), warn_noop },
+            quote! { #[derive(Clone)] pub struct S(u8); }
+        )
+        .to_string(),
+        quote! {
+            #[cfg(any(test, doc))] #[derive(Clone)] pub struct S(pub u8);
+            #[cfg(not(any(test, doc)))] #[derive(Clone)] pub struct S(u8);
+        }
+        .to_string()
+    );
+    // The warning item is gated along with the item.
+    let out = set_visibility(quote! { pub if test, warn_noop }, quote! { pub fn f() {} });
+    assert_eq!(out.to_string().matches("# [cfg (test)]").count(), 2);
+    assert!(set_visibility(quote! { pub if }, quote! { fn f() {} })
+        .to_string()
+        .contains("expected a `cfg` predicate after `if`"));
+}
+
+#[test]
+fn recursive_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), recursive },
+            quote! {
+                mod outer {
+                    #![allow(dead_code)]
+                    use super::Foo;
+                    fn f() {}
+                    impl Foo {
+                        fn g() {}
+                    }
+                    println!();
+                    pub mod inner {
+                        pub(super) struct S;
+                        mod file;
+                    }
+                }
+            }
+        )
+        .to_string(),
+        quote! {
+            pub(crate) mod outer {
+                #![allow(dead_code)]
+                pub(crate) use super::Foo;
+                pub(crate) fn f() {}
+                impl Foo {
+                    fn g() {}
+                }
+                println!();
+                pub(crate) mod inner {
+                    pub(crate) struct S;
+                    pub(crate) mod file;
+                }
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub, recursive }, quote! { mod m; }).to_string(),
+        quote! { pub mod m; }.to_string()
+    );
+    assert!(
+        set_visibility(quote! { pub, recursive }, quote! { fn f() {} })
+            .to_string()
+            .contains("`recursive` can only be used on a `mod`")
+    );
+}
+
+#[test]
+fn recursive_only_if_private_test() {
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), recursive, only_if_private },
+            quote! {
+                pub mod outer {
+                    fn f() {}
+                    pub fn g() {}
+                    mod inner { fn h() {} pub(super) fn i() {} }
+                }
+            }
+        )
+        .to_string(),
+        quote! {
+            pub mod outer {
+                pub(crate) fn f() {}
+                pub fn g() {}
+                pub(crate) mod inner { pub(crate) fn h() {} pub(super) fn i() {} }
+            }
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn expose_test() {
+    let item = quote! { #[inline] pub fn f() {} };
+    assert_eq!(
+        expose_to_crate(TokenStream::new(), item.clone()).to_string(),
+        quote! { #[inline] pub(crate) fn f() {} }.to_string()
+    );
+    assert_eq!(
+        expose_to_super(TokenStream::new(), item.clone()).to_string(),
+        quote! { #[inline] pub(super) fn f() {} }.to_string()
+    );
+    assert!(expose_to_crate(quote! { pub }, item.clone())
+        .to_string()
+        .contains("`expose_to_crate` doesn't take an argument"));
+    assert!(expose_to_super(quote! { pub }, item)
+        .to_string()
+        .contains("`expose_to_super` doesn't take an argument"));
+}
+
+#[test]
+fn keep_if_missing_arg_test() {
+    let item = quote! { pub(super) fn f() {} };
+    for input in [quote! {}, quote! { , }] {
+        assert_eq!(
+            set_visibility(input.clone(), item.clone()).to_string(),
+            quote! { fn f() {} }.to_string()
+        );
+        assert_eq!(
+            set_visibility(quote! { #input keep_if_missing_arg }, item.clone()).to_string(),
+            item.to_string()
+        );
+    }
+    assert_eq!(
+        set_visibility(quote! { keep_if_missing_arg, pub(crate) }, item.clone()).to_string(),
+        quote! { pub(crate) fn f() {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(self), keep_if_missing_arg }, item).to_string(),
+        quote! { fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn visibility_for_feature_test() {
+    let out = visibility_for_feature(
+        quote! { "expose", public = pub, private = pub(crate) },
+        quote! { #[inline] fn f(){} },
+    );
+    assert_eq!(
+        out.to_string(),
+        quote! {
+            #[cfg(feature = "expose")] #[inline] pub fn f(){}
+            #[cfg(not(feature = "expose"))] #[inline] pub(crate) fn f(){}
+        }
+        .to_string()
+    );
+
+    // Options can come in either order, with a trailing comma.
+    let out = visibility_for_feature(
+        quote! { "expose", private = , public = pub(super), },
+        quote! { pub struct S; },
+    );
+    assert_eq!(
+        out.to_string(),
+        quote! {
+            #[cfg(feature = "expose")] pub(super) struct S;
+            #[cfg(not(feature = "expose"))] struct S;
+        }
+        .to_string()
+    );
+
+    for input in [
+        quote! { expose, public = pub, private = pub(crate) },
+        quote! { "expose", public = pub },
+        quote! { "expose", public = pub, private = pub(crate), other },
+        quote! { "expose", public = pub(nowhere), private = pub(crate) },
+        quote! {},
+    ] {
+        let out = visibility_for_feature(input, quote! { fn f(){} }).to_string();
+        assert!(out.contains("compile_error"), "{out}");
+        assert!(out.ends_with(&quote! { fn f(){} }.to_string()), "{out}");
+    }
+}
+
+#[test]
+fn descend_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(crate), descend }, quote! { { pub fn f(){} } }).to_string(),
+        quote! { { pub(crate) fn f(){} } }.to_string()
+    );
+    assert_eq!(
+        VisibilityRewriter::new()
+            .descend()
+            .apply(quote! { { #[inline] pub fn f(){} } })
+            .to_string(),
+        quote! { { #[inline] fn f(){} } }.to_string()
+    );
+
+    // Only a lone group is descended into.
+    assert_eq!(
+        set_visibility(quote! { pub, descend }, quote! { fn f() {} }).to_string(),
+        quote! { pub fn f() {} }.to_string()
+    );
+}
+
+#[test]
+fn debug_test() {
+    assert_eq!(
+        set_visibility(quote! { pub(crate), debug }, quote! { pub fn f(){} }).to_string(),
+        quote! {
+            pub(crate) fn f(){}
+            const _: &str = "`set_visibility` changed `pub` to `pub (crate)`";
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { debug }, quote! { #[inline] pub(super) fn f(){} }).to_string(),
+        quote! {
+            #[inline] fn f(){}
+            const _: &str = "`set_visibility` changed `pub (super)` to private";
+        }
+        .to_string()
+    );
+    let out = set_visibility(quote! { pub(crate) }, quote! { pub fn f(){} }).to_string();
+    assert!(!out.contains("const _"), "{out}");
+
+    assert_eq!(
+        set_visibility(quote! { pub(crate) if test, debug }, quote! { fn f(){} }).to_string(),
+        quote! {
+            #[cfg(test)] pub(crate) fn f(){}
+            #[cfg(test)] const _: &str = "`set_visibility` changed private to `pub (crate)`";
+            #[cfg(not(test))] fn f(){}
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn default_test() {
+    assert_eq!(
+        strip_visibility(quote! { pub default fn f(){} }).to_string(),
+        quote! { default fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, quote! { pub default fn f(){} }).to_string(),
+        quote! { pub(crate) default fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub }, quote! { default fn f(){} }).to_string(),
+        quote! { pub default fn f(){} }.to_string()
+    );
+
+    // `default pub` isn't valid, so the `pub` isn't taken as the visibility.
+    assert_eq!(
+        strip_visibility(quote! { default pub fn f(){} }).to_string(),
+        quote! { default pub fn f(){} }.to_string()
+    );
+    let out = set_visibility(quote! { pub(crate) }, quote! { default pub fn f(){} }).to_string();
+    assert!(
+        out.contains("the visibility goes before `default`"),
+        "{out}"
+    );
+    assert!(
+        out.ends_with(&quote! { default pub fn f(){} }.to_string()),
+        "{out}"
+    );
+}
+
+#[test]
+fn preset_test() {
+    assert_eq!(
+        set_visibility(quote! { internal }, quote! { pub fn f(){} }).to_string(),
+        quote! { pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { public }, quote! { fn f(){} }).to_string(),
+        quote! { pub fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { "internal" }, quote! { fn f(){} }).to_string(),
+        quote! { pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        clamp_visibility(quote! { internal }, quote! { pub fn f(){} }).to_string(),
+        quote! { pub(crate) fn f(){} }.to_string()
+    );
+    let out = set_visibility(quote! { internal::a }, quote! { fn f(){} }).to_string();
+    assert!(out.contains("compile_error"), "{out}");
+}
+
+#[test]
+fn missing_item_test() {
+    for (item, message) in [
+        (quote! {}, "expected an item"),
+        (quote! { pub(crate) }, "expected an item"),
+        (quote! { #[inline] }, "expected an item after attributes"),
+        (
+            quote! { #[inline] #[cfg(test)] pub },
+            "expected an item after attributes",
+        ),
+    ] {
+        let error = try_set_visibility(quote! { pub }, item).unwrap_err();
+        assert!(matches!(error, VisibilityError::UnsupportedItem { .. }));
+        assert_eq!(error.to_string(), message);
+    }
+
+    let out = set_visibility(quote! { pub }, quote! { #[inline] }).to_string();
+    assert_eq!(
+        out,
+        quote! { ::core::compile_error!{"expected an item after attributes"} #[inline] }
+            .to_string()
+    );
+}
+
+/// A xorshift generator, so the fuzz test is reproducible without
+/// extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Builds a random stream by splicing together sample streams and
+/// tokens that often confuse item parsers.
+fn random_stream(rng: &mut Rng, corpus: &[TokenStream], depth: usize) -> TokenStream {
+    let pieces = [
+        quote! { pub },
+        quote! { in },
+        quote! { # },
+        quote! { : },
+        quote! { :: },
+        quote! { ; },
+        quote! { , },
+        quote! { = },
+        quote! { < },
+        quote! { > },
+        quote! { -> },
+        quote! { ! },
+        quote! { crate },
+        quote! { "pub" },
+        quote! { 'a },
+        quote! { r#pub },
+    ];
+    let mut out = TokenStream::new();
+    for _ in 0..rng.below(6) {
+        match rng.below(4) {
+            0 => out.extend(corpus[rng.below(corpus.len())].clone()),
+            1 if depth > 0 => {
+                let delimiter = [
+                    proc_macro2::Delimiter::Parenthesis,
+                    proc_macro2::Delimiter::Bracket,
+                    proc_macro2::Delimiter::Brace,
+                    proc_macro2::Delimiter::None,
+                ][rng.below(4)];
+                let inner = random_stream(rng, corpus, depth - 1);
+                out.extend([TokenTree::from(proc_macro2::Group::new(delimiter, inner))]);
+            }
+            _ => out.extend(pieces[rng.below(pieces.len())].clone()),
+        }
+    }
+    out
+}
+
+#[test]
+fn fuzz_test() {
+    let samples = get_sample_streams();
+    let corpus: Vec<TokenStream> = samples
+        .prefixes()
+        .chain(samples.bases.iter())
+        .chain(samples.non_items.iter())
+        .cloned()
+        .collect();
+    let options = [
+        quote! {},
+        quote! { fields },
+        quote! { field = "a" },
+        quote! { fields = pub },
+        quote! { recursive },
+        quote! { only_if_private },
+        quote! { descend },
+        quote! { debug },
+        quote! { if test },
+    ];
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..5000 {
+        let input = random_stream(&mut rng, &corpus, 2);
+        let item = random_stream(&mut rng, &corpus, 3);
+        let option = &options[rng.below(options.len())];
+        let outputs = [
+            set_visibility(quote! { #input, #option }, item.clone()),
+            strip_visibility(item.clone()),
+            clamp_visibility(input.clone(), item.clone()),
+            set_visibility_block(quote! { #input; #item }),
+        ];
+        for out in outputs {
+            let text = out.to_string();
+            assert!(
+                text.parse::<TokenStream>().is_ok(),
+                "{input} / {item}: {text}"
+            );
+        }
+    }
+}
+
+#[test]
+fn strip_all_visibility_test() {
+    let items = quote! {
+        pub(crate) fn f() {}
+        pub struct S { pub a: u8 }
+        pub struct T(pub u8, pub(super) u8);
+        pub const N: usize = 3;
+        #[derive(Debug)] pub enum E { A, B }
+        pub use foo::{Bar, Baz};
+        impl S { pub fn m(&self) {} }
+        pub mod m { pub fn g() {} }
+        pub(in crate::a) type Z = u8;
+    };
+    assert_eq!(
+        strip_all_visibility(items.clone()).to_string(),
+        quote! {
+            fn f() {}
+            struct S { pub a: u8 }
+            struct T(pub u8, pub(super) u8);
+            const N: usize = 3;
+            #[derive(Debug)] enum E { A, B }
+            use foo::{Bar, Baz};
+            impl S { pub fn m(&self) {} }
+            mod m { pub fn g() {} }
+            type Z = u8;
+        }
+        .to_string()
+    );
+    assert_eq!(
+        strip_all_visibility(quote! { fields; #items }).to_string(),
+        quote! {
+            fn f() {}
+            struct S { a: u8 }
+            struct T(u8, u8);
+            const N: usize = 3;
+            #[derive(Debug)] enum E { A, B }
+            use foo::{Bar, Baz};
+            impl S { pub fn m(&self) {} }
+            mod m { pub fn g() {} }
+            type Z = u8;
+        }
+        .to_string()
+    );
+    assert!(strip_all_visibility(TokenStream::new()).is_empty());
+}
+
+#[test]
+fn strip_doc_hidden_test() {
+    let item = quote! { #[inline] #[doc(hidden)] #[must_use] pub fn f(){} };
+    assert_eq!(
+        set_visibility(quote! { pub(crate), strip_doc_hidden }, item.clone()).to_string(),
+        quote! { #[inline] #[must_use] pub(crate) fn f(){} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { strip_doc_hidden }, item.clone()).to_string(),
+        quote! { #[inline] #[must_use] fn f(){} }.to_string()
+    );
+    // Public items keep it, and so does everything without the flag.
+    assert_eq!(
+        set_visibility(quote! { pub, strip_doc_hidden }, item.clone()).to_string(),
+        item.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub(crate) }, item).to_string(),
+        quote! { #[inline] #[doc(hidden)] #[must_use] pub(crate) fn f(){} }.to_string()
+    );
+    // Other `doc` attributes are kept.
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate), strip_doc_hidden },
+            quote! { #[doc = "hi"] #[doc(alias = "g")] fn f(){} }
+        )
+        .to_string(),
+        quote! { #[doc = "hi"] #[doc(alias = "g")] pub(crate) fn f(){} }.to_string()
+    );
+}
+
+#[test]
+fn relex_assertion_test() {
+    let samples = get_sample_streams();
+    let comb = iproduct!(
+        samples.visibilities.iter(),
+        samples.bases.iter().chain(samples.non_items.iter()),
+        samples.prefixes()
+    );
+
+    // In debug builds, each successful rewrite is checked on the way out.
+    for (v, b, p) in comb {
+        let _ = try_set_visibility(v.to_owned(), quote! { #[inline] #p #b });
+    }
+}
+
+#[test]
+fn normalize_test() {
+    for (input, expected) in [
+        (quote! { pub(in crate), normalize }, quote! { pub(crate) }),
+        (quote! { normalize, pub(in super) }, quote! { pub(super) }),
+        (quote! { pub(in self), normalize }, quote! {}),
+        (
+            quote! { pub(in crate::a), normalize },
+            quote! { pub(in crate::a) },
+        ),
+        (quote! { pub(in crate) }, quote! { pub(in crate) }),
+        // A module of the 2015 edition, which `pub(foo)` wouldn't name.
+        (quote! { pub(in foo), normalize }, quote! { pub(in foo) }),
+    ] {
+        assert_eq!(
+            set_visibility(input, quote! { pub fn f(){} }).to_string(),
+            quote! { #expected fn f(){} }.to_string()
+        );
+    }
+    assert_eq!(
+        set_visibility(
+            quote! { pub, fields = pub(in crate), normalize },
+            quote! { struct S(u8); }
+        )
+        .to_string(),
+        quote! { pub struct S(pub(crate) u8); }.to_string()
+    );
+}
+
+#[test]
+fn max_tokens_test() {
+    // `fn f ( x : u8 ) { x ; }`, not counting the groups.
+    let item = quote! { fn f(x: u8) { x; } };
+    assert_eq!(
+        set_visibility(quote! { pub, max_tokens = 7 }, item.clone()).to_string(),
+        quote! { pub #item }.to_string()
+    );
+    let error = try_set_visibility(quote! { pub, max_tokens = 6 }, item.clone()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the item has more tokens than `max_tokens` allows"
+    );
+    assert!(matches!(error, VisibilityError::UnsupportedItem { .. }));
+    assert!(
+        set_visibility(quote! { pub, max_tokens = 100_000 }, item.clone())
+            .to_string()
+            .starts_with("pub")
+    );
+
+    for input in [
+        quote! { pub, max_tokens = "7" },
+        quote! { pub, max_tokens = -1 },
+    ] {
+        let out = set_visibility(input, item.clone()).to_string();
+        assert!(out.contains("compile_error"), "{out}");
+    }
+}
+
+#[test]
+fn index_test() {
+    let item = quote! { struct T(u8, pub u16); };
+    assert_eq!(
+        set_visibility(quote! { pub(crate), index = 0 }, item.clone()).to_string(),
+        quote! { struct T(pub(crate) u8, pub u16); }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { index = 1 }, item.clone()).to_string(),
+        quote! { struct T(u8, u16); }.to_string()
+    );
+    assert_eq!(
+        VisibilityRewriter::new()
+            .with_visibility(quote! { pub })
+            .index(1)
+            .apply(quote! { struct T<F: Fn(u8)>(#[doc = "hi"] F, (u8, u16),) where F: Copy; })
+            .to_string(),
+        quote! { struct T<F: Fn(u8)>(#[doc = "hi"] F, pub (u8, u16),) where F: Copy; }.to_string()
+    );
+
+    for (input, item, message) in [
+        (
+            quote! { pub, index = 2 },
+            item.clone(),
+            "`index` is out of range for this struct",
+        ),
+        (
+            quote! { pub, index = 0 },
+            quote! { struct U; },
+            "`index` is out of range",
+        ),
+        (
+            quote! { pub, index = 0 },
+            quote! { struct S { a: u8 } },
+            "use `field` instead",
+        ),
+        (
+            quote! { pub, index = "0" },
+            item.clone(),
+            "expected a field index",
+        ),
+    ] {
+        let out = set_visibility(input, item.clone()).to_string();
+        assert!(out.contains(message), "{out}");
+        assert!(out.ends_with(&item.to_string()), "{out}");
+    }
+}
+
+#[test]
+fn empty_argument_test() {
+    let item = quote! { pub(crate) fn f() {} };
+    for input in ["", "  \n\t", "// a comment\n", "/* a comment */", "\"\""] {
+        let input: TokenStream = input.parse().unwrap();
+        assert_eq!(
+            set_visibility(input, item.clone()).to_string(),
+            quote! { fn f() {} }.to_string()
+        );
+    }
+
+    for (input, expected) in [
+        (quote! { only_if_private }, quote! { pub fn f() {} }),
+        (quote! { normalize }, quote! { fn f() {} }),
+        (quote! { , }, quote! { fn f() {} }),
+        (
+            quote! { allow_dead },
+            quote! { #[allow(dead_code)] fn f() {} },
+        ),
+    ] {
+        assert_eq!(
+            set_visibility(input, quote! { pub fn f() {} }).to_string(),
+            expected.to_string()
+        );
+    }
+    assert_eq!(
+        set_visibility(quote! { fields }, quote! { pub struct S(pub u8); }).to_string(),
+        quote! { pub struct S(u8); }.to_string()
+    );
+}
+
+#[test]
+fn block_depth_test() {
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate), depth = 1;
+            fn a() {}
+            wrap! { fn b() {} pub struct S; }
+            {
+                pub fn c() {}
+            }
+        })
+        .to_string(),
+        quote! {
+            pub(crate) fn a() {}
+            wrap! { pub(crate) fn b() {} pub(crate) struct S; }
+            {
+                pub(crate) fn c() {}
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility_block(quote! {
+            depth = 2, pub;
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] { fn f() {} } else { fn f() {} }
+            }
+        })
+        .to_string(),
+        quote! {
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] { pub fn f() {} } else { pub fn f() {} }
+            }
+        }
+        .to_string()
+    );
+
+    // One level down are the branches, which are left as they are.
+    let input = quote! {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] { fn f() {} } else if #[cfg(windows)] { fn g() {} } else { fn h() {} }
+        }
+    };
+    assert_eq!(
+        set_visibility_block(quote! { pub, depth = 1; #input }).to_string(),
+        input.to_string()
+    );
+
+    // Without a depth, macro invocations are left as they are.
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub;
+            wrap! { fn b() {} }
+            outer::wrap!(fn c() {});
+            fn d() {}
+        })
+        .to_string(),
+        quote! {
+            wrap! { fn b() {} }
+            outer::wrap!(fn c() {});
+            pub fn d() {}
+        }
+        .to_string()
+    );
+    for input in [
+        quote! { pub, depth = x; fn f() {} },
+        quote! { pub, depth =; fn f() {} },
+    ] {
+        assert!(set_visibility_block(input)
+            .to_string()
+            .contains("expected a nesting depth"));
+    }
+}
+
+#[test]
+fn from_env_test() {
+    let lookup = |name: &str| match name {
+        "CRATE" => Some("pub(crate)".to_owned()),
+        "PRESET" => Some("internal".to_owned()),
+        "EMPTY" => Some(String::new()),
+        "BROKEN" => Some("pub(".to_owned()),
+        "WRONG" => Some("pub(nowhere)".to_owned()),
+        "QUOTED" => Some("\"pub\"".to_owned()),
+        _ => None,
+    };
+    let item = quote! { pub fn f() {} };
+    for (input, expected) in [
+        (
+            quote! { from_env = "CRATE" },
+            quote! { pub(crate) fn f() {} },
+        ),
+        (
+            quote! { from_env = "PRESET" },
+            quote! { pub(crate) fn f() {} },
+        ),
+        (quote! { from_env = "EMPTY" }, quote! { fn f() {} }),
+        (quote! { from_env = "UNSET" }, quote! { fn f() {} }),
+        (
+            quote! { only_if_private, from_env = "CRATE" },
+            quote! { pub fn f() {} },
+        ),
+    ] {
+        let input = resolve_from_env(input, lookup);
+        assert_eq!(
+            set_visibility(input, item.clone()).to_string(),
+            expected.to_string()
+        );
+    }
+
+    for (input, message) in [
+        (
+            quote! { from_env = "BROKEN" },
+            "expected a string containing a visibility",
+        ),
+        (
+            quote! { from_env = "QUOTED" },
+            "expected a string containing a visibility",
+        ),
+        (
+            quote! { from_env = "WRONG" },
+            "expected a visibility such as `pub` or `pub(crate)`",
+        ),
+        (
+            quote! { from_env = CRATE },
+            "expected an environment variable name string",
+        ),
+    ] {
+        let input = resolve_from_env(input, lookup);
+        let error = try_set_visibility(input, item.clone()).unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+
+    let error = try_set_visibility(quote! { from_env = "CRATE" }, item).unwrap_err();
+    assert!(error.to_string().contains("call `resolve_from_env` first"));
+}
+
+#[test]
+fn nightly_trait_test() {
+    assert_eq!(
+        strip_visibility(quote! { pub auto trait Foo {} }).to_string(),
+        quote! { auto trait Foo {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub(crate) },
+            quote! { pub unsafe auto trait Foo {} }
+        )
+        .to_string(),
+        quote! { pub(crate) unsafe auto trait Foo {} }.to_string()
+    );
+    assert_eq!(
+        set_visibility(quote! { pub }, quote! { trait Bar = Clone + Send; }).to_string(),
+        quote! { pub trait Bar = Clone + Send; }.to_string()
+    );
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            pub trait Bar<T> = Iterator<Item = T> where T: Copy;
+            auto trait Foo {}
+        })
+        .to_string(),
+        quote! {
+            pub(crate) trait Bar<T> = Iterator<Item = T> where T: Copy;
+            pub(crate) auto trait Foo {}
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn test_visible_test() {
+    assert_eq!(
+        test_visible(quote! { pub(crate) }, quote! { #[inline] pub fn f() {} }).to_string(),
+        quote! {
+            #[cfg(test)] #[inline] pub(crate) fn f() {}
+            #[cfg(not(test))] #[inline] fn f() {}
+        }
+        .to_string()
+    );
+    assert_eq!(
+        test_visible(quote! { pub, allow_dead }, quote! { struct S; }).to_string(),
+        quote! {
+            #[cfg(test)] pub struct S;
+            #[cfg(not(test))] #[allow(dead_code)] struct S;
+        }
+        .to_string()
+    );
+    assert_eq!(
+        test_visible(
+            quote! { pub(crate), fields },
+            quote! { pub struct S { pub a: u8 } }
+        )
+        .to_string(),
+        quote! {
+            #[cfg(test)] pub struct S { pub(crate) a: u8 }
+            #[cfg(not(test))] pub struct S { a: u8 }
+        }
+        .to_string()
+    );
+
+    for (input, item, message) in [
+        (quote! {}, quote! { fn f() {} }, "expected the visibility"),
+        (
+            quote! { pub(nowhere) },
+            quote! { fn f() {} },
+            "expected a visibility",
+        ),
+        (quote! { pub }, quote! { impl Foo {} }, "`impl` blocks"),
+    ] {
+        let out = test_visible(input, item.clone()).to_string();
+        assert!(out.contains(message), "{out}");
+        assert!(out.ends_with(&item.to_string()), "{out}");
+    }
+}
+
+#[test]
+fn test_visible_options_test() {
+    let out = test_visible(
+        quote! { pub(crate) if feature = "x", warn_noop },
+        quote! { fn f() {} },
+    );
+    let items = split_items(out);
+    // Every item is behind `cfg(test)` or its negation, so only one
+    // copy of `f` and of its warning is compiled.
+    for item in &items {
+        let attribute = item[..2]
+            .iter()
+            .cloned()
+            .collect::<TokenStream>()
+            .to_string();
+        assert!(
+            attribute == "# [cfg (test)]" || attribute == "# [cfg (not (test))]",
+            "{}",
+            item.iter().cloned().collect::<TokenStream>()
+        );
+    }
+    let out: TokenStream = items.into_iter().flatten().collect();
+    assert_eq!(
+        out.to_string(),
+        quote! {
+            #[cfg(test)] #[cfg(feature = "x")] pub(crate) fn f() {}
+            #[cfg(test)] #[cfg(not(feature = "x"))] fn f() {}
+            #[cfg(not(test))] #[cfg(feature = "x")] fn f() {}
+            #[cfg(not(test))] #[cfg(feature = "x")] const _: () = {
+                #[deprecated(note = "`set_visibility` doesn't change the visibility of this item")]
+                struct SetVisibilityNoop;
+                let _ = SetVisibilityNoop;
+            };
+            #[cfg(not(test))] #[cfg(not(feature = "x"))] fn f() {}
+        }
+        .to_string()
+    );
+
+    let out = test_visible(quote! { pub, warn_noop }, quote! { fn f() {} }).to_string();
+    assert!(out.contains("# [cfg (not (test))] const _"), "{out}");
+}
+
+#[test]
+fn macro_invocation_test() {
+    for item in [
+        quote! { wrap! { fn f() {} } },
+        quote! { ::outer::wrap!(fn f() {}); },
+        quote! { #[cfg(unix)] pub wrap![]; },
+    ] {
+        let error = try_set_visibility(quote! { pub }, item.clone()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("macro invocations don't take a visibility"),
+            "{item}"
+        );
+    }
+    assert!(try_set_visibility(quote! { pub }, quote! { fn f() -> bool { !true } }).is_ok());
+
+    assert_eq!(
+        set_visibility_block(quote! {
+            pub(crate);
+            impl Foo {
+                wrap!();
+                fn f() {}
+            }
+        })
+        .to_string(),
+        quote! {
+            impl Foo {
+                wrap!();
+                pub(crate) fn f() {}
+            }
+        }
+        .to_string()
+    );
+    assert_eq!(
+        set_visibility(
+            quote! { pub, recursive },
+            quote! { mod m { wrap! {} macro_rules! m { () => {} } fn f() {} } }
+        )
+        .to_string(),
+        quote! { pub mod m { wrap! {} macro_rules! m { () => {} } pub fn f() {} } }.to_string()
+    );
+}
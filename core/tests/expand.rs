@@ -0,0 +1,74 @@
+//! Snapshot tests of the `set_visibility` expansion.
+//!
+//! Each `tests/expand/<name>.rs` holds an item with a `#[set_visibility]`
+//! attribute, and `<name>.expanded.rs` holds what it should expand to.
+//! For invalid input, that includes the `compile_error!` invocation, so
+//! the diagnostics are snapshotted too.
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use std::fs;
+use std::path::Path;
+use visibility_attribute_core::set_visibility;
+
+/// Splits a leading `#[set_visibility(...)]` off of an item.
+fn split_attribute(input: TokenStream) -> (TokenStream, TokenStream) {
+    let mut tt_iter = input.into_iter();
+    let (Some(TokenTree::Punct(_)), Some(TokenTree::Group(attribute))) =
+        (tt_iter.next(), tt_iter.next())
+    else {
+        panic!("fixtures start with `#[set_visibility(...)]`");
+    };
+    let Some(TokenTree::Group(args)) = attribute.stream().into_iter().nth(1) else {
+        panic!("fixtures start with `#[set_visibility(...)]`");
+    };
+    (args.stream(), tt_iter.collect())
+}
+
+/// The spans of every token in a stream, including nested ones. Groups
+/// are named after their delimiter, as their contents may change.
+fn spans(input: TokenStream) -> Vec<(String, Span)> {
+    let mut out = Vec::new();
+    for tt in input {
+        match tt {
+            TokenTree::Group(x) => {
+                out.push((format!("{:?}", x.delimiter()), x.span()));
+                out.extend(spans(x.stream()));
+            }
+            x => out.push((x.to_string(), x.span())),
+        }
+    }
+    out
+}
+
+#[test]
+fn expand_test() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/expand");
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if name.ends_with(".expanded.rs") {
+            continue;
+        }
+
+        let input: TokenStream = fs::read_to_string(&path).unwrap().parse().unwrap();
+        let expected_path = path.with_extension("expanded.rs");
+        let expected: TokenStream = fs::read_to_string(expected_path).unwrap().parse().unwrap();
+        let (args, item) = split_attribute(input);
+        let output = set_visibility(args.clone(), item.clone());
+        assert_eq!(output.to_string(), expected.to_string(), "{name}");
+
+        // Every token gets a span from the input, rather than one made
+        // up during expansion, so the output is as stable as the source.
+        let mut input_spans = spans(args.clone());
+        input_spans.extend(spans(item.clone()));
+        for (token, span) in spans(output) {
+            assert!(
+                input_spans.iter().any(|(_, x)| x.start() == span.start()),
+                "{name}: {token}"
+            );
+        }
+        count += 1;
+    }
+    assert!(count > 0);
+}
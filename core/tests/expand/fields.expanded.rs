@@ -0,0 +1,5 @@
+#[derive(Clone)]
+pub struct Point {
+    pub(super) x: i32,
+    pub(super) y: i32,
+}
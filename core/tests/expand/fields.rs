@@ -0,0 +1,6 @@
+#[set_visibility(pub(super), fields)]
+#[derive(Clone)]
+pub struct Point {
+    pub x: i32,
+    y: i32,
+}
@@ -0,0 +1,2 @@
+::core::compile_error! { "`impl` blocks don't take a visibility; apply `set_visibility` to the associated items instead" }
+impl Foo {}
@@ -0,0 +1,2 @@
+#[set_visibility(pub)]
+impl Foo {}
@@ -0,0 +1,2 @@
+::core::compile_error! { "expected a visibility such as `pub` or `pub(crate)`" }
+fn f() {}
@@ -0,0 +1,2 @@
+#[set_visibility(not_a_visibility)]
+fn f() {}
@@ -0,0 +1,4 @@
+::core::compile_error! { "`macro_rules!` macros don't have a visibility; use `#[macro_export]` instead" }
+macro_rules! m {
+    () => {};
+}
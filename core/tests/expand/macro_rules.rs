@@ -0,0 +1,4 @@
+#[set_visibility(pub)]
+macro_rules! m {
+    () => {};
+}
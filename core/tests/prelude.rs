@@ -0,0 +1,28 @@
+use visibility_attribute_core::prelude::*;
+
+#[test]
+fn prelude_test() {
+    let item: proc_macro2::TokenStream = "pub fn f() {}".parse().unwrap();
+    let visibility = peek_visibility(&item).unwrap();
+    assert_eq!(VisLevel::from_tokens(visibility), Some(VisLevel::Public));
+
+    let stripped = strip_visibility(item.clone());
+    assert_eq!(stripped.to_string(), "fn f () { }");
+    let crate_visibility = "pub(crate)".parse().unwrap();
+    let prepended = prepend_visibility(&crate_visibility, stripped);
+    assert_eq!(prepended.to_string(), "pub (crate) fn f () { }");
+
+    let output = set_visibility(crate_visibility.clone(), item.clone());
+    assert_eq!(output.to_string(), prepended.to_string());
+    let rewriter = VisibilityRewriter::new().with_visibility(crate_visibility);
+    assert_eq!(
+        rewriter.apply(item.clone()).to_string(),
+        prepended.to_string()
+    );
+
+    let error: VisibilityError = try_set_visibility("crate::a".parse().unwrap(), item).unwrap_err();
+    assert!(error
+        .into_compile_error()
+        .to_string()
+        .contains("compile_error"));
+}
@@ -0,0 +1,32 @@
+#![cfg(feature = "testing")]
+
+use visibility_attribute_core::testing::{
+    assert_visibility, inner_set_visibility2, remove_visibility2,
+};
+
+#[test]
+fn testing_feature_test() {
+    let item = "pub fn f() {}".parse().unwrap();
+    let (visibility, rest) = remove_visibility2(item);
+    assert_eq!(visibility.to_string(), "pub");
+    assert_eq!(rest.to_string(), "fn f () { }");
+
+    let output = inner_set_visibility2("pub(crate)".parse().unwrap(), rest);
+    assert_eq!(output.to_string(), "pub (crate) fn f () { }");
+}
+
+#[test]
+fn assert_visibility_test() {
+    assert_visibility("pub(crate) fn f(){}".parse().unwrap(), "pub(crate)");
+    assert_visibility("#[inline] pub fn f(){}".parse().unwrap(), "pub");
+    assert_visibility("fn f(){}".parse().unwrap(), "");
+
+    let item = inner_set_visibility2("pub(super)".parse().unwrap(), "fn f(){}".parse().unwrap());
+    assert_visibility(item, "pub(super)");
+}
+
+#[test]
+#[should_panic(expected = "unexpected visibility")]
+fn assert_visibility_mismatch_test() {
+    assert_visibility("pub(crate) fn f(){}".parse().unwrap(), "pub");
+}
@@ -25,6 +25,15 @@
 //! either. See [Cargo's documentation](https://doc.rust-lang.org/cargo/reference/index.html)
 //! for details.
 //! 
+//! The visibility has to be changed where the item is declared. A
+//! conditional `pub use` of a private item can't stand in for it, as a
+//! re-export can't be more visible than the item itself, and it can't
+//! reuse the item's name in the same module either. For the same
+//! reason, there is no option to keep the item as it is and add a more
+//! visible alias of it alongside. To make an item more visible only
+//! under a condition, use `#[cfg_attr(condition, set_visibility(..))]`
+//! on the item itself, as above.
+//!
 //! ### Development dependency
 //! When only using the `visibility_attribute` crate for doc tests
 //! or related targets, list it under
@@ -43,12 +52,84 @@
 //! [dev-dependencies]
 //! visibility_attribute = { git = "https://url.git" }
 //! ```
-
-#[cfg(test)]
-mod tests;
+//!
+//! ### Using the logic directly
+//! The attributes are thin wrappers around functions in the
+//! `visibility_attribute_core` crate, which works on `proc_macro2`
+//! tokens. Other procedural macros and build scripts can depend on it
+//! instead.
+//!
+//! ### Nightly diagnostics
+//! With the `nightly` feature, errors are reported through the unstable
+//! [`Diagnostic`](https://doc.rust-lang.org/proc_macro/struct.Diagnostic.html)
+//! API instead of `compile_error!`. This requires a nightly compiler.
+//!
+//! If the `VISIBILITY_ATTRIBUTE_DEBUG` environment variable is also set
+//! when building, `set_visibility` adds a note to each item it rewrites
+//! with the visibility before and after. The variable is tracked, so
+//! setting or clearing it reruns the macro.
+#![cfg_attr(
+    feature = "nightly",
+    feature(proc_macro_diagnostic, proc_macro_tracked_env)
+)]
 
 #[proc_macro_attribute]
 /// Replace the visibility modifier with the input.
+///
+/// The input must be a visibility modifier, or nothing to make the
+/// item private. Anything else is reported as a compile error. The
+/// visibility can also be given as a string literal such as
+/// `"pub(crate)"`, which is easier for other macros to generate.
+/// `inherit` leaves the visibility as it is, which is useful as a
+/// branch of a generated chain of `cfg_attr`s.
+///
+/// `#[set_visibility]` without parentheses makes the item private too.
+/// The compiler passes the same empty input for it as for
+/// `#[set_visibility()]`, so the two can't be told apart and neither is
+/// reported as a mistake. Use [`make_private`](macro@make_private) to
+/// make the intent clear.
+///
+/// The presets `internal` and `public` stand for `pub(crate)` and `pub`,
+/// so the intent of each item reads the same across a crate. Presets are
+/// built in, as a procedural macro can't look up anything defined by the
+/// crate it is used in.
+///
+/// # Options
+/// Options go before or after the visibility, separated by commas.
+/// - `allow_dead`: Allow dead code on the item if it is made private.
+/// - `debug`: Add a `const _: &str` item to the output describing the
+///   visibility before and after. This only works on items outside of
+///   `impl` blocks.
+/// - `descend`: Set the visibility of the item inside a brace group, if
+///   the group is the whole input. This helps when another macro passes
+///   the item along wrapped up.
+/// - `fields`: Set the visibility of each field of a struct or union, or each
+///   declaration of an `extern` block, instead of the item itself.
+/// - `fields = vis`: Set the visibility of each field of a struct to
+///   `vis`, and of the struct itself to the main visibility.
+/// - `field = "name"`: Set the visibility of just the named field of a
+///   struct.
+/// - `from_env = "NAME"`: Use the visibility held by the environment
+///   variable `NAME` when building, such as `pub(crate)`, or make the
+///   item private if it isn't set. Without the `nightly` feature, a
+///   `const _` item reading the variable with `option_env!` is added, so
+///   that changing it rebuilds the crate. This only works on items
+///   outside of `impl` blocks.
+/// - `index = n`: Set the visibility of just the field of a tuple struct
+///   at index `n`, counting from 0.
+/// - `keep_if_missing_arg`: Leave the item alone, instead of making it
+///   private, if there is no visibility.
+/// - `max_tokens = n`: Report an error if the item has more than `n`
+///   tokens, as a guard against rewriting huge generated items.
+/// - `normalize`: Write `pub(in crate)` and `pub(in super)` as
+///   `pub(crate)` and `pub(super)`.
+/// - `only_if_private`: Leave items that already have a visibility alone.
+/// - `recursive`: Also set the visibility of the items in a module,
+///   including those in nested modules.
+/// - `strip_doc_hidden`: Remove `#[doc(hidden)]` from the item if it
+///   ends up less visible than `pub`.
+/// - `warn_noop`: Cause a deprecation warning if the visibility wouldn't
+///   change. This only works on items outside of `impl` blocks.
 /// 
 /// # Examples
 /// ```
@@ -73,53 +154,694 @@ mod tests;
 /// 
 /// assert_eq!(squaring::square(5), 25); // shouldn't compile!
 /// ```
+/// ```
+/// mod shapes {
+///     use visibility_attribute::set_visibility;
+///     #[set_visibility(pub(super), fields)]
+///     pub struct Square {
+///         side: i32,
+///     }
+/// }
+///
+/// let square = shapes::Square { side: 5 };
+/// assert_eq!(square.side, 5);
+/// ```
+/// ```
+/// #![deny(deprecated)]
+/// use visibility_attribute::set_visibility;
+/// #[set_visibility(pub, warn_noop)]
+/// fn square(num: i32) -> i32 {
+///     num * num
+/// }
+/// # assert_eq!(square(5), 25);
+/// ```
+/// ``` compile_fail
+/// #![deny(deprecated)]
+/// use visibility_attribute::set_visibility;
+/// #[set_visibility(pub, warn_noop)]
+/// pub fn square(num: i32) -> i32 {
+///     num * num
+/// }
+/// ```
+/// The visibility can be followed by `if` and a `cfg` predicate, as in
+/// `#[set_visibility(pub(crate) if feature = "expose")]`. This is a
+/// shorthand for `cfg_attr`, but the macro runs either way.
+/// ```
+/// mod squaring {
+///     use visibility_attribute::set_visibility;
+///     #[set_visibility(pub(super) if not(any()))]
+///     fn square(num: i32) -> i32 {
+///         num * num
+///     }
+///
+///     #[set_visibility(pub(super) if any())]
+///     fn cube(num: i32) -> i32 {
+///         num * num * num
+///     }
+///
+///     pub fn call_cube(num: i32) -> i32 {
+///         cube(num)
+///     }
+/// }
+///
+/// assert_eq!(squaring::square(5), 25);
+/// assert_eq!(squaring::call_cube(5), 125);
+/// ```
+///
+/// # Errors
+/// Each of these is reported with `compile_error!`, and the item is kept
+/// as it was.
+///
+/// Input that isn't a visibility or an option:
+/// ``` compile_fail
+/// use visibility_attribute::set_visibility;
+/// #[set_visibility(not_a_visibility)]
+/// fn square(num: i32) -> i32 {
+///     num * num
+/// }
+/// ```
+/// An item that can't have a visibility, such as an `impl` block:
+/// ``` compile_fail
+/// use visibility_attribute::set_visibility;
+/// struct Square(i32);
+///
+/// #[set_visibility(pub)]
+/// impl Square {
+///     fn area(&self) -> i32 {
+///         self.0 * self.0
+///     }
+/// }
+/// ```
+/// A `macro_rules!` macro, which is exported with `#[macro_export]`
+/// instead:
+/// ``` compile_fail
+/// use visibility_attribute::set_visibility;
+/// #[set_visibility(pub)]
+/// macro_rules! square {
+///     ($num:expr) => {
+///         $num * $num
+///     };
+/// }
+/// ```
 pub fn set_visibility(
     input: proc_macro::TokenStream,
     annotated_item: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    inner_set_visibility(input.into(), annotated_item.into()).into()
-}
-
-macro_rules! impl_macro {
-    ($sv:ident, $rv:ident, $pmc:ident) => {
-        /// Implements the actual logic.
-        fn $sv(
-            input: $pmc::TokenStream,
-            annotated_item: $pmc::TokenStream,
-        ) -> $pmc::TokenStream {
-            let mut out_stream = input;
-            out_stream.extend($rv(annotated_item));
-            out_stream
-        }
+    let annotated_item = proc_macro2::TokenStream::from(annotated_item);
+    let (input, tracking) = resolve_from_env(input.into());
+    let mut output = visibility_attribute_core::set_visibility(input, annotated_item.clone());
+    trace(&annotated_item, &output);
+    output.extend(tracking);
+    finish(output)
+}
 
-        /// Removes the visibility modifier from a TokenTree iterable.
-        ///
-        /// It returns an iterator rather than a TokenStream.
-        /// This function should be agnostic to spans.
-        fn $rv(
-            input: impl IntoIterator<Item = $pmc::TokenTree>,
-        ) -> impl Iterator<Item = $pmc::TokenTree> {
-            let mut tt_iter = input.into_iter().peekable();
-
-            if tt_iter
-                .next_if(|x| match x {
-                    $pmc::TokenTree::Ident(y) => *y.to_string() == *"pub",
-                    _ => false,
-                })
-                .is_none()
-            {
-                return tt_iter;
-            }
+#[proc_macro_attribute]
+/// A shorter name for [`macro@set_visibility`].
+///
+/// It takes the same input. Attributes can also be renamed on import,
+/// as in `use visibility_attribute::set_visibility as internal_vis;`.
+///
+/// # Examples
+/// ```
+/// mod squaring {
+///     use visibility_attribute::vis;
+///     #[vis(pub(super))]
+///     fn square(num: i32) -> i32 {
+///         num * num
+///     }
+/// }
+///
+/// mod cubing {
+///     use visibility_attribute::set_visibility as internal_vis;
+///     #[internal_vis(pub(super))]
+///     fn cube(num: i32) -> i32 {
+///         num * num * num
+///     }
+/// }
+///
+/// assert_eq!(squaring::square(5), 25);
+/// assert_eq!(cubing::cube(5), 125);
+/// ```
+pub fn vis(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    set_visibility(input, annotated_item)
+}
+
+#[proc_macro]
+/// Replace the visibility modifier of every item in the block.
+///
+/// The block starts with the argument [`macro@set_visibility`] would
+/// take, followed by a `;`. The methods and other associated items of
+/// inherent `impl` blocks are rewritten too.
+/// Macro invocations are left as they are.
+///
+/// A `depth = n` option also rewrites the items inside brace groups that
+/// aren't items, such as the body of another macro invocation, up to `n`
+/// groups deep. The items in the branches of `cfg_if!` are 2 groups deep.
+///
+/// # Examples
+/// ```
+/// mod shapes {
+///     use visibility_attribute::set_visibility_block;
+///     set_visibility_block! {
+///         pub(super);
+///
+///         fn square(num: i32) -> i32 {
+///             num * num
+///         }
+///
+///         pub fn cube(num: i32) -> i32 {
+///             num * num * num
+///         }
+///     }
+/// }
+///
+/// assert_eq!(shapes::square(5), 25);
+/// assert_eq!(shapes::cube(5), 125);
+/// ```
+/// ```
+/// mod shapes {
+///     use visibility_attribute::set_visibility_block;
+///     set_visibility_block! {
+///         pub(super);
+///
+///         enum Shape {
+///             Square(u32),
+///         }
+///
+///         impl Shape {
+///             fn area(&self) -> u32 {
+///                 let Self::Square(side) = self;
+///                 side * side
+///             }
+///         }
+///     }
+/// }
+///
+/// assert_eq!(shapes::Shape::Square(5).area(), 25);
+/// ```
+/// ```
+/// macro_rules! wrap {
+///     ($($item:item)*) => { $($item)* };
+/// }
+///
+/// mod shapes {
+///     use visibility_attribute::set_visibility_block;
+///     set_visibility_block! {
+///         pub(super), depth = 1;
+///
+///         wrap! {
+///             fn square(num: i32) -> i32 {
+///                 num * num
+///             }
+///         }
+///     }
+/// }
+///
+/// assert_eq!(shapes::square(5), 25);
+/// ```
+pub fn set_visibility_block(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    // Only the argument before the `;` can hold `from_env`.
+    let mut tokens: Vec<proc_macro2::TokenTree> =
+        proc_macro2::TokenStream::from(input).into_iter().collect();
+    let end = tokens
+        .iter()
+        .position(|x| matches!(x, proc_macro2::TokenTree::Punct(y) if y.as_char() == ';'))
+        .unwrap_or(tokens.len());
+    let items = tokens.split_off(end);
+    let (mut input, tracking) = resolve_from_env(tokens.into_iter().collect());
+    input.extend(items);
+    let mut output = visibility_attribute_core::set_visibility_block(input);
+    output.extend(tracking);
+    finish(output)
+}
 
-            tt_iter.next_if(|x| match x {
-                $pmc::TokenTree::Group(y) => y.delimiter() == $pmc::Delimiter::Parenthesis,
-                _ => false,
-            });
+#[proc_macro]
+/// Remove the visibility modifier of every item in the block.
+///
+/// Start the block with `fields;` to make the fields of structs private
+/// too. Only top-level items are changed, which is enough for a private
+/// copy of a module's contents.
+///
+/// # Examples
+/// ```
+/// mod shapes {
+///     use visibility_attribute::strip_all_visibility;
+///     strip_all_visibility! {
+///         pub fn square(num: i32) -> i32 {
+///             num * num
+///         }
+///
+///         pub struct Square(pub i32);
+///     }
+///
+///     pub fn area(side: i32) -> i32 {
+///         square(Square(side).0)
+///     }
+/// }
+///
+/// assert_eq!(shapes::area(5), 25);
+/// ```
+/// ``` compile_fail
+/// mod shapes {
+///     use visibility_attribute::strip_all_visibility;
+///     strip_all_visibility! {
+///         pub fn square(num: i32) -> i32 {
+///             num * num
+///         }
+///     }
+/// }
+///
+/// assert_eq!(shapes::square(5), 25); // shouldn't compile!
+/// ```
+pub fn strip_all_visibility(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::strip_all_visibility(
+        input.into(),
+    ))
+}
 
-            tt_iter
+#[proc_macro_attribute]
+/// Remove the visibility modifier, making the item private.
+///
+/// This is the same as `#[set_visibility()]`, but reads better inside
+/// `cfg_attr`. It doesn't take an argument.
+///
+/// # Examples
+/// ```
+/// mod helpers {
+///     use visibility_attribute::make_private;
+///     #[cfg_attr(not(test), make_private)]
+///     pub fn helper() -> i32 {
+///         5
+///     }
+///
+///     pub fn call_helper() -> i32 {
+///         helper()
+///     }
+/// }
+///
+/// assert_eq!(helpers::call_helper(), 5);
+/// ```
+/// ``` compile_fail
+/// mod helpers {
+///     use visibility_attribute::make_private;
+///     #[cfg_attr(not(test), make_private)]
+///     pub fn helper() -> i32 {
+///         5
+///     }
+/// }
+///
+/// assert_eq!(helpers::helper(), 5); // shouldn't compile!
+/// ```
+/// ``` compile_fail
+/// use visibility_attribute::make_private;
+/// #[make_private(pub)]
+/// pub fn helper() -> i32 {
+///     5
+/// }
+/// ```
+pub fn make_private(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::make_private(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Replace the visibility modifier with `pub`.
+///
+/// This is the same as `#[set_visibility(pub)]`, but reads better inside
+/// `cfg_attr`. It doesn't take an argument.
+///
+/// # Examples
+/// ```
+/// mod internals {
+///     use visibility_attribute::make_public;
+///     #[make_public]
+///     fn internal() -> i32 {
+///         5
+///     }
+///
+///     // Only public in this crate's unit tests.
+///     #[cfg_attr(test, make_public)]
+///     fn tested() -> i32 {
+///         6
+///     }
+///
+///     pub fn call_tested() -> i32 {
+///         tested()
+///     }
+/// }
+///
+/// assert_eq!(internals::internal(), 5);
+/// assert_eq!(internals::call_tested(), 6);
+/// ```
+/// ``` compile_fail
+/// use visibility_attribute::make_public;
+/// #[make_public(pub(crate))]
+/// fn internal() -> i32 {
+///     5
+/// }
+/// ```
+pub fn make_public(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::make_public(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Replace the visibility modifier with `pub(crate)`.
+///
+/// This is the same as `#[set_visibility(pub(crate))]`, but reads better
+/// inside `cfg_attr`. It doesn't take an argument.
+///
+/// # Examples
+/// ```
+/// mod internals {
+///     use visibility_attribute::expose_to_crate;
+///     #[cfg_attr(not(test), expose_to_crate)]
+///     fn internal() -> i32 {
+///         5
+///     }
+/// }
+///
+/// assert_eq!(internals::internal(), 5);
+/// ```
+pub fn expose_to_crate(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::expose_to_crate(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Replace the visibility modifier with `pub(super)`.
+///
+/// This is the same as `#[set_visibility(pub(super))]`, but reads better
+/// inside `cfg_attr`. It doesn't take an argument.
+///
+/// # Examples
+/// ```
+/// mod outer {
+///     mod internals {
+///         use visibility_attribute::expose_to_super;
+///         #[cfg_attr(not(test), expose_to_super)]
+///         fn internal() -> i32 {
+///             5
+///         }
+///     }
+///
+///     pub fn call_internal() -> i32 {
+///         internals::internal()
+///     }
+/// }
+///
+/// assert_eq!(outer::call_internal(), 5);
+/// ```
+/// ``` compile_fail
+/// mod internals {
+///     use visibility_attribute::expose_to_super;
+///     #[expose_to_super(pub)]
+///     fn internal() -> i32 {
+///         5
+///     }
+/// }
+/// ```
+pub fn expose_to_super(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::expose_to_super(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Replace the visibility modifier with the input if that would narrow it.
+///
+/// Items that are already at most as visible as the input are left
+/// alone, so this never widens an API. When the two visibilities can't
+/// be compared, such as `pub(super)` and `pub(in crate::a)`, the item is
+/// left alone too.
+///
+/// # Examples
+/// ```
+/// mod squaring {
+///     use visibility_attribute::clamp_visibility;
+///     #[clamp_visibility(pub(crate))]
+///     pub fn square(num: i32) -> i32 {
+///         num * num
+///     }
+///
+///     #[clamp_visibility(pub)]
+///     fn cube(num: i32) -> i32 {
+///         num * num * num
+///     }
+///
+///     pub fn call_cube(num: i32) -> i32 {
+///         cube(num)
+///     }
+/// }
+///
+/// assert_eq!(squaring::square(5), 25);
+/// assert_eq!(squaring::call_cube(5), 125);
+/// ```
+pub fn clamp_visibility(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::clamp_visibility(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Pick the visibility depending on a feature of the crate.
+///
+/// The first argument names the feature. The item gets the `public`
+/// visibility when that feature is enabled and the `private` one
+/// otherwise. This saves writing a pair of `cfg_attr` attributes.
+///
+/// # Examples
+/// ```
+/// mod internals {
+///     use visibility_attribute::visibility_for_feature;
+///     #[visibility_for_feature("expose", public = pub, private = pub(crate))]
+///     fn internal() -> i32 {
+///         5
+///     }
+/// }
+///
+/// assert_eq!(internals::internal(), 5);
+/// ```
+pub fn visibility_for_feature(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    finish(visibility_attribute_core::visibility_for_feature(
+        input.into(),
+        annotated_item.into(),
+    ))
+}
+
+#[proc_macro_attribute]
+/// Replace the visibility modifier with the input only in tests.
+///
+/// The input is what [`macro@set_visibility`] would take. Outside of
+/// `cfg(test)`, the item is made private instead. The item is emitted
+/// twice, behind `#[cfg(test)]` and `#[cfg(not(test))]`, so only one copy
+/// is ever compiled and the two don't clash.
+///
+/// Unlike `#[cfg_attr(test, set_visibility(..))]`, this also rewrites the
+/// item outside of tests, so the visibility written on it is ignored.
+/// The procedural macro runs in every build as a result.
+///
+/// # Examples
+/// ```
+/// mod parser {
+///     use visibility_attribute::test_visible;
+///     #[test_visible(pub(crate))]
+///     fn parse_digit(digit: char) -> Option<u32> {
+///         digit.to_digit(10)
+///     }
+///
+///     pub fn parse(digit: char) -> Option<u32> {
+///         parse_digit(digit)
+///     }
+/// }
+///
+/// #[cfg(test)]
+/// fn parse_five() -> Option<u32> {
+///     parser::parse_digit('5')
+/// }
+///
+/// assert_eq!(parser::parse('5'), Some(5));
+/// ```
+/// Doctests aren't built with `cfg(test)`, so here the item is private:
+/// ``` compile_fail
+/// mod parser {
+///     use visibility_attribute::test_visible;
+///     #[test_visible(pub(crate))]
+///     fn parse_digit(digit: char) -> Option<u32> {
+///         digit.to_digit(10)
+///     }
+/// }
+///
+/// assert_eq!(parser::parse_digit('5'), Some(5)); // shouldn't compile!
+/// ```
+pub fn test_visible(
+    input: proc_macro::TokenStream,
+    annotated_item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let (input, tracking) = resolve_from_env(input.into());
+    let mut output = visibility_attribute_core::test_visible(input, annotated_item.into());
+    output.extend(tracking);
+    finish(output)
+}
+
+/// Replaces the `from_env` options of `input` with the visibilities they
+/// name.
+///
+/// `std::env::var` isn't tracked, so without the `nightly` feature the
+/// second stream has a `const _` item for each variable read, which
+/// reads it again with `option_env!`. That tells cargo to rebuild when
+/// the variable changes.
+fn resolve_from_env(
+    input: proc_macro2::TokenStream,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let names = std::cell::RefCell::new(Vec::new());
+    let input = visibility_attribute_core::resolve_from_env(input, |name| {
+        names.borrow_mut().push(proc_macro2::Literal::string(name));
+        env_var(name)
+    });
+    let mut tracking = proc_macro2::TokenStream::new();
+    if !cfg!(feature = "nightly") {
+        for name in names.into_inner() {
+            let item =
+                format!("const _: ::core::option::Option<&str> = ::core::option_env!({name});");
+            tracking.extend(item.parse::<proc_macro2::TokenStream>().unwrap());
         }
+    }
+    (input, tracking)
+}
+
+/// Gets the value of an environment variable when building.
+#[cfg(not(feature = "nightly"))]
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Gets the value of an environment variable when building, and tells
+/// the compiler to rebuild if it changes.
+#[cfg(feature = "nightly")]
+fn env_var(name: &str) -> Option<String> {
+    proc_macro::tracked::env_var(name).ok()
+}
+
+/// Notes how `set_visibility` changed an item, if the
+/// `VISIBILITY_ATTRIBUTE_DEBUG` environment variable is set.
+#[cfg(not(feature = "nightly"))]
+fn trace(_: &proc_macro2::TokenStream, _: &proc_macro2::TokenStream) {}
+
+/// Notes how `set_visibility` changed an item, if the
+/// `VISIBILITY_ATTRIBUTE_DEBUG` environment variable is set.
+#[cfg(feature = "nightly")]
+fn trace(annotated_item: &proc_macro2::TokenStream, output: &proc_macro2::TokenStream) {
+    use proc_macro::{Diagnostic, Level};
+
+    if proc_macro::tracked::env_var("VISIBILITY_ATTRIBUTE_DEBUG").is_err() {
+        return;
+    }
+    // Errors are reported on their own, and nothing was changed.
+    let Some(message) = visibility_attribute_core::describe_change(annotated_item, output) else {
+        return;
     };
+    let span = annotated_item.clone().into_iter().next().map(|x| x.span());
+    let span = span.unwrap_or_else(proc_macro2::Span::call_site).unwrap();
+    Diagnostic::spanned(span, Level::Note, message).emit();
+}
+
+/// Converts the output of the core crate back to the compiler's tokens.
+#[cfg(not(feature = "nightly"))]
+fn finish(output: proc_macro2::TokenStream) -> proc_macro::TokenStream {
+    output.into()
+}
+
+/// Converts the output of the core crate back to the compiler's tokens.
+///
+/// Each top-level `::core::compile_error!{"message"}` is emitted as a
+/// diagnostic at the same span and removed from the output.
+#[cfg(feature = "nightly")]
+fn finish(output: proc_macro2::TokenStream) -> proc_macro::TokenStream {
+    use proc_macro::{Diagnostic, Level};
+    use proc_macro2::TokenTree;
+
+    let tokens: Vec<TokenTree> = output.into_iter().collect();
+    let mut out_stream = proc_macro2::TokenStream::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let path = tokens[i..].iter().take(7).cloned();
+        let is_compile_error = *path.collect::<proc_macro2::TokenStream>().to_string()
+            == *":: core :: compile_error !";
+        match tokens.get(i + 7) {
+            Some(TokenTree::Group(args)) if is_compile_error => {
+                let message = args.stream().to_string();
+                let span = tokens[i].span().unwrap();
+                Diagnostic::spanned(span, Level::Error, message.trim_matches('"')).emit();
+                i += 8;
+            }
+            _ => {
+                out_stream.extend([tokens[i].clone()]);
+                i += 1;
+            }
+        }
+    }
+    out_stream.into()
 }
-use impl_macro; 
 
-impl_macro!(inner_set_visibility, remove_visibility, proc_macro);
+/// The failing half of `tests/module_hierarchy.rs`, which can only be
+/// checked by a doctest. Items are out of reach past their visibility:
+/// ``` compile_fail
+/// mod outer {
+///     pub mod inner {
+///         use visibility_attribute::set_visibility;
+///         #[set_visibility(pub(in crate::outer))]
+///         pub fn to_outer() -> i32 {
+///             3
+///         }
+///     }
+/// }
+///
+/// fn main() {
+///     outer::inner::to_outer();
+/// }
+/// ```
+/// ``` compile_fail
+/// mod outer {
+///     use visibility_attribute::make_private;
+///     #[make_private]
+///     pub fn hidden() -> i32 {
+///         6
+///     }
+/// }
+///
+/// fn main() {
+///     outer::hidden();
+/// }
+/// ```
+#[cfg(doctest)]
+struct ModuleHierarchy;
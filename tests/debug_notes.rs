@@ -0,0 +1,36 @@
+//! Tests that items still expand as usual while `set_visibility` notes
+//! each change, as it does with `VISIBILITY_ATTRIBUTE_DEBUG` set.
+//!
+//! The variable is set by the `[env]` table of `.cargo/config.toml`, but
+//! is only read with the `nightly` feature.
+
+#![cfg(feature = "nightly")]
+
+mod inner {
+    use visibility_attribute::set_visibility;
+
+    #[set_visibility(pub(crate))]
+    fn shown() -> i32 {
+        1
+    }
+
+    #[set_visibility(pub(crate) if not(any()))]
+    fn shown_if() -> i32 {
+        2
+    }
+
+    #[set_visibility(pub(crate) if any())]
+    fn hidden_if() -> i32 {
+        3
+    }
+
+    pub fn call_hidden_if() -> i32 {
+        hidden_if()
+    }
+}
+
+#[test]
+fn debug_notes_test() {
+    assert_eq!(inner::shown() + inner::shown_if(), 3);
+    assert_eq!(inner::call_hidden_if(), 3);
+}
@@ -0,0 +1,37 @@
+//! Tests of `from_env` through the whole procedural macro pipeline.
+//!
+//! The test harness can't set environment variables for the compiler,
+//! so the set variable comes from the `[env]` table of
+//! `.cargo/config.toml`.
+
+mod inner {
+    use visibility_attribute::set_visibility;
+
+    #[set_visibility(from_env = "VISIBILITY_ATTRIBUTE_UNSET_FOR_TESTS")]
+    pub fn hidden() -> i32 {
+        1
+    }
+
+    pub fn call_hidden() -> i32 {
+        hidden() + block_hidden()
+    }
+
+    #[set_visibility(from_env = "VISIBILITY_ATTRIBUTE_SET_FOR_TESTS")]
+    fn shown() -> i32 {
+        3
+    }
+
+    visibility_attribute::set_visibility_block! {
+        from_env = "VISIBILITY_ATTRIBUTE_UNSET_FOR_TESTS";
+
+        pub fn block_hidden() -> i32 {
+            2
+        }
+    }
+}
+
+#[test]
+fn from_env_test() {
+    assert_eq!(inner::call_hidden(), 3);
+    assert_eq!(inner::shown(), 3);
+}
@@ -0,0 +1,31 @@
+//! Tests of the attributes in files brought in with `include!`, as
+//! generated by build scripts.
+
+mod generated {
+    use visibility_attribute::set_visibility;
+
+    include!("include/generated.rs");
+}
+
+/// The expansion snapshots of the core crate, which `expand.rs` there
+/// checks against the output of `set_visibility`.
+mod expanded_fields {
+    include!("../core/tests/expand/fields.expanded.rs");
+}
+
+mod expanded_fn {
+    include!("../core/tests/expand/fn.expanded.rs");
+}
+
+#[test]
+fn include_test() {
+    assert_eq!(generated::generated(), 1);
+    assert_eq!(generated::Generated { value: 2 }.value, 2);
+}
+
+#[test]
+fn expanded_include_test() {
+    let point = expanded_fields::Point { x: 1, y: 2 };
+    assert_eq!(point.clone().x + point.y, 3);
+    expanded_fn::f();
+}
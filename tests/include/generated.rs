@@ -0,0 +1,11 @@
+// Stands in for a file generated by a build script.
+
+#[set_visibility(pub(super))]
+fn generated() -> i32 {
+    1
+}
+
+#[set_visibility(pub(super), fields = pub(super))]
+struct Generated {
+    value: i32,
+}
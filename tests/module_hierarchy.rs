@@ -0,0 +1,95 @@
+//! Tests of the attributes on real items, through the whole procedural
+//! macro pipeline.
+//!
+//! Only what compiles can be tested here. That `to_outer` and `hidden`
+//! can't be reached from outside of `outer` is checked by the
+//! `compile_fail` doctests on `ModuleHierarchy` in `src/lib.rs` instead.
+
+use visibility_attribute::{make_private, set_visibility};
+
+mod outer {
+    use visibility_attribute::{make_private, set_visibility};
+
+    pub mod inner {
+        use visibility_attribute::set_visibility;
+
+        #[set_visibility(pub(super))]
+        fn to_parent() -> i32 {
+            1
+        }
+
+        #[set_visibility(pub(crate))]
+        fn to_crate() -> i32 {
+            2
+        }
+
+        #[set_visibility(pub(in crate::outer))]
+        fn to_outer() -> i32 {
+            3
+        }
+
+        #[set_visibility(pub(crate), fields = pub(crate))]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        pub fn point() -> Point {
+            Point { x: 4, y: 5 }
+        }
+
+        pub fn call_to_parent() -> i32 {
+            to_parent()
+        }
+    }
+
+    #[make_private]
+    pub fn hidden() -> i32 {
+        6
+    }
+
+    #[set_visibility]
+    pub fn also_hidden() -> i32 {
+        7
+    }
+
+    #[set_visibility()]
+    pub fn hidden_by_parens() -> i32 {
+        8
+    }
+
+    pub fn call_inner() -> i32 {
+        inner::to_parent() + inner::to_outer()
+    }
+
+    pub fn call_hidden() -> i32 {
+        hidden() + also_hidden() + hidden_by_parens()
+    }
+}
+
+#[make_private]
+pub fn top_level() -> i32 {
+    8
+}
+
+#[set_visibility(pub)]
+fn made_public() -> i32 {
+    9
+}
+
+#[test]
+fn module_hierarchy_test() {
+    assert_eq!(outer::call_inner(), 4);
+    assert_eq!(outer::inner::call_to_parent(), 1);
+    assert_eq!(outer::inner::to_crate(), 2);
+
+    let point = outer::inner::point();
+    assert_eq!(point.x + point.y, 9);
+}
+
+#[test]
+fn private_item_test() {
+    assert_eq!(outer::call_hidden(), 21);
+    assert_eq!(top_level(), 8);
+    assert_eq!(made_public(), 9);
+}
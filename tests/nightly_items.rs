@@ -0,0 +1,22 @@
+//! Tests of the attributes on item forms only nightly compilers accept.
+
+#![cfg(feature = "nightly")]
+#![feature(auto_traits, trait_alias)]
+
+// The items are in their own file, so stable compilers don't parse them.
+#[path = "nightly_items/traits.rs"]
+mod traits;
+
+fn is_marked<T: ?Sized + traits::Marker>() -> bool {
+    true
+}
+
+fn widen<T: traits::Number>(x: T) -> i64 {
+    x.into()
+}
+
+#[test]
+fn nightly_items_test() {
+    assert!(is_marked::<u8>());
+    assert_eq!(widen(5u8), 5);
+}
@@ -0,0 +1,7 @@
+use visibility_attribute::set_visibility;
+
+#[set_visibility(pub(crate))]
+auto trait Marker {}
+
+#[set_visibility(pub(crate))]
+trait Number = Copy + Into<i64>;
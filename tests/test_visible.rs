@@ -0,0 +1,22 @@
+//! Tests of `test_visible`, which are built with `cfg(test)`.
+//!
+//! That the items are private otherwise is checked by the `compile_fail`
+//! doctest of the attribute, as doctests aren't built with `cfg(test)`.
+
+mod parser {
+    use visibility_attribute::test_visible;
+
+    #[test_visible(pub(crate))]
+    fn parse_digit(digit: char) -> Option<u32> {
+        digit.to_digit(10)
+    }
+
+    #[test_visible(pub(crate), fields = pub(crate))]
+    struct Digit(u32);
+}
+
+#[test]
+fn test_visible_test() {
+    assert_eq!(parser::parse_digit('5'), Some(5));
+    assert_eq!(parser::Digit(5).0, 5);
+}